@@ -0,0 +1,253 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::c02_key_hash::space_max;
+use crate::c02_ring_error::RingError;
+
+/// Current load of a node against the bound it must stay under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeLoad{
+    pub node: u64,
+    pub load: usize,
+    pub capacity: usize,
+}
+
+/// Consistent hashing with bounded loads (Mirrokni, Thorup & Zadimoghaddam).
+///
+/// No node holds more than ceil(c * m / n) of the m resources spread over n
+/// nodes. A resource goes to its successor as on the plain ring unless that
+/// node is full, in which case it walks forward to the first node with room.
+pub struct BoundedLoadRing{
+    nodes: BTreeMap<u64, Vec<u64>>, // token -> resources placed on it
+    placement: HashMap<u64, u64>,   // resource -> token
+    c: f64,
+    max: u64,
+}
+
+impl BoundedLoadRing{
+    /// `c` is the allowed overload factor and must be at least 1.
+    pub fn new(k: u32, c: f64) -> Self{
+        if c < 1.0{
+            panic!("Load factor c must be at least 1");
+        }
+        Self { nodes: BTreeMap::new(), placement: HashMap::new(), c, max: space_max(k) }
+    }
+
+    pub fn is_in_legal_range(&self, hash_value: u64) -> bool {
+        hash_value <= self.max
+    }
+
+    /// Clockwise distance from `a` to `b`, wrapping modulo 2^k.
+    pub fn distance(&self, a: u64, b: u64) -> u64{
+        b.wrapping_sub(a) & self.max
+    }
+
+    /// Load bound every node has to respect with the current resources.
+    pub fn capacity(&self) -> usize{
+        self.capacity_for(self.placement.len())
+    }
+
+    fn capacity_for(&self, resources: usize) -> usize{
+        if self.nodes.is_empty(){
+            return 0;
+        }
+        (self.c * resources as f64 / self.nodes.len() as f64).ceil() as usize
+    }
+
+    pub fn loads(&self) -> Vec<NodeLoad>{
+        let capacity = self.capacity();
+        self.nodes.iter()
+            .map(|(node, resources)| NodeLoad { node: *node, load: resources.len(), capacity })
+            .collect()
+    }
+
+    /// Node a stored resource currently sits on.
+    pub fn lookup_node(&self, hash_value: u64) -> Option<u64>{
+        self.placement.get(&hash_value).copied()
+    }
+
+    /// Nodes from the successor of `hash_value` onwards, once round the ring.
+    fn walk(&self, hash_value: u64) -> impl Iterator<Item = u64> + '_{
+        self.nodes.range(hash_value..).chain(self.nodes.range(..hash_value)).map(|(node, _)| *node)
+    }
+
+    fn place(&mut self, hash_value: u64, start: u64, capacity: usize){
+        let node = self.walk(start).find(|node| self.nodes[node].len() < capacity)
+            .expect("c >= 1 always leaves room on some node");
+        self.nodes.get_mut(&node).unwrap().push(hash_value);
+        self.placement.insert(hash_value, node);
+    }
+
+    /// Storing a resource that is already stored leaves it where it is.
+    pub fn add_resource(&mut self, hash_value: u64) -> Result<(), RingError>{
+        if !self.is_in_legal_range(hash_value){
+            return Err(RingError::OutOfRange(hash_value));
+        }
+        if self.nodes.is_empty(){
+            return Err(RingError::EmptyRing);
+        }
+        if !self.placement.contains_key(&hash_value){
+            let capacity = self.capacity_for(self.placement.len() + 1);
+            self.place(hash_value, hash_value, capacity);
+        }
+        Ok(())
+    }
+
+    pub fn remove_resource(&mut self, hash_value: u64){
+        let Some(node) = self.placement.remove(&hash_value) else{
+            return;
+        };
+        self.nodes.get_mut(&node).unwrap().retain(|r| *r != hash_value);
+        self.shed_overload();
+    }
+
+    /// With fewer resources the bound can drop below a node's load; push the
+    /// excess forward until every node is back under it.
+    fn shed_overload(&mut self){
+        let capacity = self.capacity();
+        let overloaded = self.nodes.iter()
+            .filter(|(_, resources)| resources.len() > capacity)
+            .map(|(node, _)| *node)
+            .collect::<Vec<u64>>();
+        for node in overloaded{
+            while self.nodes[&node].len() > capacity{
+                // the resource that walked the farthest to get here goes first
+                let resources = &self.nodes[&node];
+                let (index, hash_value) = resources.iter().enumerate()
+                    .max_by_key(|(_, r)| (self.distance(**r, node), **r))
+                    .map(|(index, r)| (index, *r))
+                    .unwrap();
+                self.nodes.get_mut(&node).unwrap().swap_remove(index);
+                let start = node.wrapping_add(1) & self.max;
+                self.place(hash_value, start, capacity);
+            }
+        }
+    }
+
+    /// Adds a node and re-places every resource in hash order under the new,
+    /// tighter bound. Returns how many resources changed node.
+    pub fn add_node(&mut self, hash_value: u64) -> Result<usize, RingError>{
+        if !self.is_in_legal_range(hash_value){
+            return Err(RingError::OutOfRange(hash_value));
+        }
+        if self.nodes.contains_key(&hash_value){
+            return Err(RingError::DuplicateNode(hash_value));
+        }
+        self.nodes.insert(hash_value, vec![]);
+
+        let before = std::mem::take(&mut self.placement);
+        for resources in self.nodes.values_mut(){
+            resources.clear();
+        }
+        let capacity = self.capacity_for(before.len());
+        let mut keys = before.keys().copied().collect::<Vec<u64>>();
+        keys.sort();
+        for key in keys.iter(){
+            self.place(*key, *key, capacity);
+        }
+        Ok(keys.iter().filter(|key| before[key] != self.placement[key]).count())
+    }
+
+    /// Removes a node and re-places its resources; the bound only grows when
+    /// a node leaves, so nobody else has to move. The last node cannot leave
+    /// while it holds resources, as they would have nowhere to go.
+    pub fn remove_node(&mut self, hash_value: u64) -> Result<(), RingError>{
+        if !self.nodes.contains_key(&hash_value){
+            return Err(RingError::UnknownNode(hash_value));
+        }
+        if self.nodes.len() == 1 && !self.placement.is_empty(){
+            return Err(RingError::EmptyRing);
+        }
+        let mut orphaned = self.nodes.remove(&hash_value).unwrap();
+        orphaned.sort();
+        let capacity = self.capacity();
+        for key in orphaned{
+            self.place(key, key, capacity);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn assert_bounded(ring: &BoundedLoadRing){
+        for node_load in ring.loads(){
+            assert!(node_load.load <= node_load.capacity, "{:?}", node_load);
+        }
+    }
+
+    #[test]
+    fn test_skewed_keys_stay_bounded(){
+        let mut ring = BoundedLoadRing::new(16, 1.25);
+        assert_eq!(Err(RingError::EmptyRing), ring.add_resource(1000));
+        for node in [0, 16384, 32768, 49152]{
+            ring.add_node(node).unwrap();
+        }
+        assert_eq!(Err(RingError::DuplicateNode(0)), ring.add_node(0));
+        assert_eq!(Err(RingError::OutOfRange(65536)), ring.add_node(65536));
+        // every key falls in the arc owned by node 16384 on the plain ring
+        for hash_value in 1000..1400{
+            ring.add_resource(hash_value).unwrap();
+        }
+        assert_eq!(Err(RingError::OutOfRange(65536)), ring.add_resource(65536));
+        ring.add_resource(1000).unwrap();
+        assert_eq!(125, ring.capacity());
+        assert_bounded(&ring);
+        assert_eq!(Some(16384), ring.lookup_node(1000));
+        assert_eq!(400, ring.loads().iter().map(|l| l.load).sum::<usize>());
+
+        for hash_value in (1000..1400).step_by(2){
+            ring.remove_resource(hash_value);
+            assert_bounded(&ring);
+        }
+        assert_eq!(63, ring.capacity());
+
+        ring.add_node(8000).unwrap();
+        assert_bounded(&ring);
+        assert_eq!(Ok(()), ring.remove_node(16384));
+        assert_eq!(Err(RingError::UnknownNode(16384)), ring.remove_node(16384));
+        assert_bounded(&ring);
+        assert_eq!(200, ring.loads().iter().map(|l| l.load).sum::<usize>());
+
+        // the last node keeps its resources rather than dropping them
+        for node in [0, 8000, 32768]{
+            ring.remove_node(node).unwrap();
+        }
+        assert_eq!(Err(RingError::EmptyRing), ring.remove_node(49152));
+        assert_eq!(200, ring.loads()[0].load);
+    }
+
+    #[test]
+    fn test_64_bit_ring(){
+        let mut ring = BoundedLoadRing::new(64, 1.0);
+        ring.add_node(u64::MAX).unwrap();
+        ring.add_node(5).unwrap();
+        for hash_value in u64::MAX - 3..=u64::MAX - 1{
+            ring.add_resource(hash_value).unwrap();
+        }
+        // node u64::MAX sheds a resource past the top of the space, onto node 5
+        ring.remove_resource(u64::MAX - 2);
+        assert_bounded(&ring);
+        assert_eq!(Some(5), ring.lookup_node(u64::MAX - 3));
+        assert_eq!(Some(u64::MAX), ring.lookup_node(u64::MAX - 1));
+    }
+
+    #[test]
+    fn test_uniform_keys_stay_near_successor(){
+        let mut ring = BoundedLoadRing::new(16, 2.0);
+        ring.add_node(0).unwrap();
+        for hash_value in (7..65536).step_by(64){
+            ring.add_resource(hash_value).unwrap();
+        }
+        for node in (4096..65536).step_by(4096){
+            ring.add_node(node).unwrap();
+            assert_bounded(&ring);
+        }
+        // with a loose bound and even keys nothing needs to spill over
+        for hash_value in (7..65536u64).step_by(64){
+            let successor = hash_value.div_ceil(4096) * 4096 % 65536;
+            assert_eq!(Some(successor), ring.lookup_node(hash_value));
+        }
+    }
+}
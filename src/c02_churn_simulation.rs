@@ -0,0 +1,257 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::c02_consistent_hasher::ConsistentHasher;
+use crate::c02_key_hash::space_max;
+use crate::c02_ring_error::RingError;
+
+/// One step of a churn schedule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChurnEvent{
+    Join(u64),
+    /// The node leaves gracefully; its keys are handed to their new owners.
+    Leave(u64),
+    /// The node disappears without handing anything over; its keys are lost.
+    Crash(u64),
+    /// An already hashed key is stored on its owner.
+    Insert(u64),
+}
+
+/// Relative frequencies of the events in a random schedule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChurnMix{
+    pub joins: u32,
+    pub leaves: u32,
+    pub crashes: u32,
+    pub inserts: u32,
+}
+
+impl Default for ChurnMix{
+    fn default() -> Self{
+        Self { joins: 2, leaves: 1, crashes: 1, inserts: 40 }
+    }
+}
+
+/// A reproducible schedule: `initial_nodes` joins, then `steps` events drawn
+/// from `mix`. Node ids and keys are values in the `k`-bit space and ids are
+/// never reused, so every scheme taking hashed ids can replay it; jump hash
+/// cannot, as its buckets only change at the tail. Once every id has been
+/// used, further joins are drawn as inserts. The last node never leaves or
+/// crashes. Panics if `k` is not between 1 and 64 or `initial_nodes` does not
+/// fit in the id space.
+pub fn random_schedule(seed: u64, k: u32, initial_nodes: usize, steps: usize, mix: ChurnMix) -> Vec<ChurnEvent>{
+    let ids = space_max(k) as u128 + 1;
+    if initial_nodes as u128 > ids{
+        panic!("{} initial nodes do not fit in a {}-bit id space", initial_nodes, k);
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut used = HashSet::new();
+    let mut members: Vec<u64> = vec![];
+    // `None` once the id space is used up
+    let mut fresh_node = |rng: &mut StdRng| loop{
+        if used.len() as u128 == ids{
+            return None;
+        }
+        let node = rng.random::<u64>() >> (64 - k);
+        if used.insert(node){
+            return Some(node);
+        }
+    };
+
+    let mut schedule = vec![];
+    for _ in 0..initial_nodes{
+        let node = fresh_node(&mut rng).expect("initial nodes fit in the id space");
+        members.push(node);
+        schedule.push(ChurnEvent::Join(node));
+    }
+    let total = mix.joins + mix.leaves + mix.crashes + mix.inserts;
+    for _ in 0..steps{
+        let roll = rng.random_range(0..total);
+        let joining = if roll < mix.joins { fresh_node(&mut rng) } else { None };
+        let event = if let Some(node) = joining{
+            members.push(node);
+            ChurnEvent::Join(node)
+        }else if roll >= mix.joins && roll < mix.joins + mix.leaves + mix.crashes && members.len() > 1{
+            let node = members.swap_remove(rng.random_range(0..members.len()));
+            if roll < mix.joins + mix.leaves { ChurnEvent::Leave(node) } else { ChurnEvent::Crash(node) }
+        }else{
+            ChurnEvent::Insert(rng.random::<u64>() >> (64 - k))
+        };
+        schedule.push(event);
+    }
+    schedule
+}
+
+/// What one event did to the placement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventRecord{
+    pub step: usize,
+    pub event: ChurnEvent,
+    pub members: usize,
+    /// Keys stored after the event.
+    pub keys: usize,
+    /// Surviving keys whose owner changed.
+    pub moved: usize,
+    /// Keys that went down with a crashed node.
+    pub lost: usize,
+    /// Most loaded member over the mean load, 0 without keys.
+    pub imbalance: f64,
+    /// Mean `ConsistentHasher::lookup_cost` over the stored keys.
+    pub lookup_cost: f64,
+}
+
+/// Replays `schedule` on `hasher`, recording every event. Keys inserted while
+/// there are no members are dropped. Stops at the first join or leave
+/// `hasher` refuses.
+pub fn simulate<H: ConsistentHasher + ?Sized>(hasher: &mut H, schedule: &[ChurnEvent]) -> Result<Vec<EventRecord>, RingError>{
+    let mut owners: HashMap<u64, u64> = HashMap::new();
+    let mut records = vec![];
+    for (step, event) in schedule.iter().enumerate(){
+        let mut lost = 0;
+        match *event{
+            ChurnEvent::Join(node) => hasher.add_node(node)?,
+            ChurnEvent::Leave(node) => hasher.remove_node(node)?,
+            ChurnEvent::Crash(node) => {
+                let before = owners.len();
+                owners.retain(|_, owner| *owner != node);
+                lost = before - owners.len();
+                hasher.remove_node(node)?;
+            },
+            ChurnEvent::Insert(key) => {
+                if let Some(owner) = hasher.locate(key){
+                    owners.insert(key, owner);
+                }
+            },
+        }
+
+        let mut moved = 0;
+        let mut loads: HashMap<u64, usize> = hasher.members().into_iter().map(|node| (node, 0)).collect();
+        let mut cost = 0;
+        for (key, owner) in owners.iter_mut(){
+            let now = hasher.locate(*key).expect("keys are only stored while there are members");
+            if now != *owner{
+                *owner = now;
+                moved += 1;
+            }
+            *loads.entry(now).or_default() += 1;
+            cost += hasher.lookup_cost(*key);
+        }
+
+        let (imbalance, lookup_cost) = if owners.is_empty(){
+            (0.0, 0.0)
+        }else{
+            let mean = owners.len() as f64 / loads.len() as f64;
+            let max = loads.values().copied().max().unwrap_or(0);
+            (max as f64 / mean, cost as f64 / owners.len() as f64)
+        };
+        records.push(EventRecord {
+            step, event: *event, members: loads.len(), keys: owners.len(), moved, lost, imbalance, lookup_cost,
+        });
+    }
+    Ok(records)
+}
+
+/// One line per event under a header, ready for a spreadsheet or pandas.
+pub fn to_csv(records: &[EventRecord]) -> String{
+    let mut csv = String::from("step,event,target,members,keys,moved,lost,imbalance,lookup_cost\n");
+    for record in records{
+        let (event, target) = match record.event{
+            ChurnEvent::Join(node) => ("join", node),
+            ChurnEvent::Leave(node) => ("leave", node),
+            ChurnEvent::Crash(node) => ("crash", node),
+            ChurnEvent::Insert(key) => ("insert", key),
+        };
+        writeln!(csv, "{},{},{},{},{},{},{},{:.4},{:.4}",
+            record.step, event, target, record.members, record.keys,
+            record.moved, record.lost, record.imbalance, record.lookup_cost).unwrap();
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::c02_maglev::Maglev;
+    use crate::c02_multi_probe::MultiProbeHash;
+    use crate::c02_rendezvous_hash::RendezvousHash;
+    use crate::c02_ringhash_2::HashRing;
+
+    #[test]
+    fn test_schedule_is_reproducible(){
+        let mix = ChurnMix::default();
+        let schedule = random_schedule(42, 20, 8, 600, mix);
+        assert_eq!(schedule, random_schedule(42, 20, 8, 600, mix));
+        assert_ne!(schedule, random_schedule(43, 20, 8, 600, mix));
+        assert!(schedule[..8].iter().all(|event| matches!(event, ChurnEvent::Join(_))));
+
+        let mut first: HashRing = HashRing::with_vnodes(20, 4);
+        let mut second: HashRing = HashRing::with_vnodes(20, 4);
+        assert_eq!(to_csv(&simulate(&mut first, &schedule).unwrap()), to_csv(&simulate(&mut second, &schedule).unwrap()));
+    }
+
+    #[test]
+    fn test_small_id_space(){
+        // a 2-bit space has room for four nodes; later joins become inserts
+        let joins_only = ChurnMix { joins: 1, leaves: 0, crashes: 0, inserts: 0 };
+        let schedule = random_schedule(1, 2, 3, 20, joins_only);
+        let mut joined = schedule.iter()
+            .filter_map(|event| if let ChurnEvent::Join(node) = event { Some(*node) } else { None })
+            .collect::<Vec<u64>>();
+        joined.sort();
+        assert_eq!(vec![0, 1, 2, 3], joined);
+        assert!(schedule[4..].iter().all(|event| matches!(event, ChurnEvent::Insert(_))));
+
+        assert!(std::panic::catch_unwind(|| random_schedule(1, 2, 5, 0, joins_only)).is_err());
+        assert!(std::panic::catch_unwind(|| random_schedule(1, 0, 1, 0, joins_only)).is_err());
+    }
+
+    #[test]
+    fn test_compare_schemes(){
+        let schedule = random_schedule(7, 20, 8, 600, ChurnMix::default());
+        let mut schemes: Vec<(&str, Box<dyn ConsistentHasher>)> = vec![
+            ("ring", Box::new(HashRing::<u64>::with_vnodes(20, 4))),
+            ("rendezvous", Box::new(RendezvousHash::new())),
+            ("maglev", Box::new(Maglev::new(4099))),
+            ("multi-probe", Box::new(MultiProbeHash::new(20, 21))),
+        ];
+        for (name, scheme) in schemes.iter_mut(){
+            let records = simulate(scheme.as_mut(), &schedule).unwrap();
+            assert_eq!(schedule.len(), records.len(), "{}", name);
+            for (record, previous) in records.iter().skip(1).zip(records.iter()){
+                match record.event{
+                    ChurnEvent::Insert(_) => {
+                        assert_eq!(0, record.moved, "{}", name);
+                        assert!(record.keys - previous.keys <= 1, "{}", name);
+                    },
+                    ChurnEvent::Crash(_) => {
+                        assert_eq!(previous.keys - record.lost, record.keys, "{}", name);
+                        // everything the crashed node held is gone rather than moved
+                        if *name != "maglev"{
+                            assert_eq!(0, record.moved, "{}", name);
+                        }
+                    },
+                    _ => assert_eq!(previous.keys, record.keys, "{}", name),
+                }
+                assert!(record.imbalance == 0.0 || record.imbalance >= 1.0, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_csv(){
+        let schedule = [ChurnEvent::Insert(5), ChurnEvent::Join(100), ChurnEvent::Insert(5), ChurnEvent::Join(200), ChurnEvent::Crash(100)];
+        let mut ring: HashRing = HashRing::new(8);
+        let csv = to_csv(&simulate(&mut ring, &schedule).unwrap());
+        let lines = csv.lines().collect::<Vec<&str>>();
+        assert_eq!("step,event,target,members,keys,moved,lost,imbalance,lookup_cost", lines[0]);
+        assert_eq!(6, lines.len());
+        assert!(lines[1].starts_with("0,insert,5,0,0,0,0,"));
+        assert!(lines[3].starts_with("2,insert,5,1,1,0,0,1.0000,"));
+        let crash = lines[5].split(',').collect::<Vec<&str>>();
+        assert_eq!(["4", "crash", "100", "1"], crash[..4]);
+        // the key either went down with node 100 or sits on node 200
+        assert_eq!(1, crash[4].parse::<usize>().unwrap() + crash[6].parse::<usize>().unwrap());
+    }
+}
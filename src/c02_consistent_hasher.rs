@@ -0,0 +1,347 @@
+use std::cell::RefCell;
+
+use crate::c02_jump_hash::JumpHash;
+use crate::c02_key_hash::space_max;
+use crate::c02_maglev::Maglev;
+use crate::c02_multi_probe::MultiProbeHash;
+use crate::c02_rendezvous_hash::RendezvousHash;
+use crate::c02_migration::{MembershipChange, ResourceMove};
+use crate::c02_ring_error::RingError;
+use crate::c02_ringhash_2::NodeRefExt as _;
+use crate::{c02_ringhash_1, c02_ringhash_2};
+
+/// What every placement scheme offers, so an application can switch between
+/// the ring, jump hash, rendezvous, Maglev and multi-probe without changing
+/// code. Nodes and keys are plain `u64`s; keys are expected to be hashed
+/// already (see `KeyHasher`). Any `u64` is a valid key: the rings fold keys
+/// into their hash space, so every scheme places the same keys.
+pub trait ConsistentHasher{
+    /// Adds `node` with unit weight. Fails with `DuplicateNode` if `node` is
+    /// already a member, or with the scheme's own error if it cannot take it.
+    fn add_node(&mut self, node: u64) -> Result<(), RingError>;
+
+    /// Fails with `UnknownNode` if `node` is not a member, or with the
+    /// scheme's own error if it cannot let it go.
+    fn remove_node(&mut self, node: u64) -> Result<(), RingError>;
+
+    /// Node responsible for `key`, or `None` when there are no nodes.
+    fn locate(&self, key: u64) -> Option<u64>;
+
+    /// Current members in ascending order.
+    fn members(&self) -> Vec<u64>;
+
+    /// Nodes or table entries `locate` has to inspect for `key`; 1 for schemes
+    /// that compute the owner directly.
+    fn lookup_cost(&self, _key: u64) -> usize{
+        1
+    }
+
+    /// Same membership and configuration, without any stored resources.
+    fn membership_copy(&self) -> Box<dyn ConsistentHasher>;
+
+    /// Moves `keys` would make if `change` were applied, or why it cannot be.
+    /// Leaves `self` as is.
+    fn plan_migration(&self, keys: &[u64], change: MembershipChange) -> Result<Vec<ResourceMove>, RingError>{
+        let mut next = self.membership_copy();
+        match change{
+            MembershipChange::Join(node) => next.add_node(node)?,
+            MembershipChange::Leave(node) => next.remove_node(node)?,
+        }
+        let mut moves = vec![];
+        for key in keys{
+            if let (Some(from), Some(to)) = (self.locate(*key), next.locate(*key)) && from != to{
+                moves.push(ResourceMove { key: *key, from, to });
+            }
+        }
+        Ok(moves)
+    }
+}
+
+impl ConsistentHasher for c02_ringhash_1::HashRing{
+    fn add_node(&mut self, node: u64) -> Result<(), RingError>{
+        self.add_physical_node(node, 1).map(|_| ())
+    }
+
+    fn remove_node(&mut self, node: u64) -> Result<(), RingError>{
+        self.remove_physical_node(node)
+    }
+
+    fn locate(&self, key: u64) -> Option<u64>{
+        self.lookup_physical_node(key & space_max(self.k())).ok()
+    }
+
+    fn members(&self) -> Vec<u64>{
+        self.physical_nodes()
+    }
+
+    /// `lookup_node` walks from the lowest token up to the owner.
+    fn lookup_cost(&self, key: u64) -> usize{
+        self.tokens_below(key & space_max(self.k())) + 1
+    }
+
+    fn membership_copy(&self) -> Box<dyn ConsistentHasher>{
+        let mut copy = Self::with_seed(self.k(), self.vnodes_per_weight(), self.seed());
+        for physical_id in self.physical_nodes(){
+            for token in self.tokens_of(physical_id){
+                copy.add_node(RefCell::new(c02_ringhash_1::Node::new_virtual(token, physical_id)).into())
+                    .expect("tokens of a ring are distinct");
+            }
+        }
+        Box::new(copy)
+    }
+}
+
+/// Finger tables are rebuilt after every membership change, as Chord's
+/// stabilisation would.
+impl ConsistentHasher for c02_ringhash_2::HashRing{
+    fn add_node(&mut self, node: u64) -> Result<(), RingError>{
+        self.add_physical_node(node, 1)?;
+        self.build_finger_tables();
+        Ok(())
+    }
+
+    fn remove_node(&mut self, node: u64) -> Result<(), RingError>{
+        self.remove_physical_node(node)?;
+        self.build_finger_tables();
+        Ok(())
+    }
+
+    fn locate(&self, key: u64) -> Option<u64>{
+        self.lookup_physical_node(key & space_max(self.k())).ok()
+    }
+
+    fn members(&self) -> Vec<u64>{
+        self.physical_nodes()
+    }
+
+    /// Hops of the finger walk.
+    fn lookup_cost(&self, key: u64) -> usize{
+        self.lookup_hops(key & space_max(self.k())).unwrap_or(0)
+    }
+
+    fn membership_copy(&self) -> Box<dyn ConsistentHasher>{
+        let mut copy = Self::with_seed(self.k(), self.vnodes_per_weight(), self.seed());
+        for node in self.nodes(){
+            copy.add_node(RefCell::new(c02_ringhash_2::Node::new_virtual(node.hash_value(), node.physical_id())).into())
+                .expect("tokens of a ring are distinct");
+        }
+        copy.build_finger_tables();
+        Box::new(copy)
+    }
+}
+
+/// Buckets are numbered `0..n` and can only come and go at the tail.
+impl ConsistentHasher for JumpHash{
+    /// Only the next bucket number can be added; a number past it is
+    /// `OutOfRange`.
+    fn add_node(&mut self, node: u64) -> Result<(), RingError>{
+        let next = self.num_buckets() as u64;
+        if node < next{
+            return Err(RingError::DuplicateNode(node));
+        }
+        if node > next{
+            return Err(RingError::OutOfRange(node));
+        }
+        self.add_bucket();
+        Ok(())
+    }
+
+    /// Only the last bucket can be removed; an earlier one is `OutOfRange`.
+    fn remove_node(&mut self, node: u64) -> Result<(), RingError>{
+        let count = self.num_buckets() as u64;
+        if node >= count{
+            return Err(RingError::UnknownNode(node));
+        }
+        if node < count - 1{
+            return Err(RingError::OutOfRange(node));
+        }
+        self.remove_bucket();
+        Ok(())
+    }
+
+    fn locate(&self, key: u64) -> Option<u64>{
+        if self.num_buckets() == 0{
+            return None;
+        }
+        Some(self.lookup_bucket(key) as u64)
+    }
+
+    fn members(&self) -> Vec<u64>{
+        (0..self.num_buckets() as u64).collect()
+    }
+
+    fn membership_copy(&self) -> Box<dyn ConsistentHasher>{
+        Box::new(Self::with_seed(self.num_buckets(), self.seed()))
+    }
+}
+
+impl ConsistentHasher for RendezvousHash{
+    fn add_node(&mut self, node: u64) -> Result<(), RingError>{
+        // the inherent `add_node` would change the weight of a member
+        if self.weight(node) > 0.0{
+            return Err(RingError::DuplicateNode(node));
+        }
+        RendezvousHash::add_node(self, node, 1.0).map(|_| ())
+    }
+
+    fn remove_node(&mut self, node: u64) -> Result<(), RingError>{
+        RendezvousHash::remove_node(self, node).map(|_| ())
+    }
+
+    fn locate(&self, key: u64) -> Option<u64>{
+        self.lookup_node(key)
+    }
+
+    fn members(&self) -> Vec<u64>{
+        self.nodes()
+    }
+
+    /// Every node is scored.
+    fn lookup_cost(&self, _key: u64) -> usize{
+        self.nodes().len()
+    }
+
+    fn membership_copy(&self) -> Box<dyn ConsistentHasher>{
+        let mut copy = Self::with_seed(self.seed());
+        for node in self.nodes(){
+            RendezvousHash::add_node(&mut copy, node, self.weight(node)).expect("members have valid weights");
+        }
+        Box::new(copy)
+    }
+}
+
+impl ConsistentHasher for Maglev{
+    fn add_node(&mut self, node: u64) -> Result<(), RingError>{
+        if self.backends().contains(&node){
+            return Err(RingError::DuplicateNode(node));
+        }
+        self.add_backend(node);
+        Ok(())
+    }
+
+    fn remove_node(&mut self, node: u64) -> Result<(), RingError>{
+        if !self.backends().contains(&node){
+            return Err(RingError::UnknownNode(node));
+        }
+        self.remove_backend(node);
+        Ok(())
+    }
+
+    fn locate(&self, key: u64) -> Option<u64>{
+        self.lookup_backend(key)
+    }
+
+    fn members(&self) -> Vec<u64>{
+        self.backends().to_vec()
+    }
+
+    fn membership_copy(&self) -> Box<dyn ConsistentHasher>{
+        let mut copy = Self::new(self.table().len());
+        for backend in self.backends(){
+            copy.add_backend(*backend);
+        }
+        Box::new(copy)
+    }
+}
+
+impl ConsistentHasher for MultiProbeHash{
+    fn add_node(&mut self, node: u64) -> Result<(), RingError>{
+        MultiProbeHash::add_node(self, node)
+    }
+
+    fn remove_node(&mut self, node: u64) -> Result<(), RingError>{
+        MultiProbeHash::remove_node(self, node)
+    }
+
+    fn locate(&self, key: u64) -> Option<u64>{
+        self.lookup_node(key)
+    }
+
+    fn members(&self) -> Vec<u64>{
+        self.nodes()
+    }
+
+    /// One ring search per probe.
+    fn lookup_cost(&self, _key: u64) -> usize{
+        self.probes() as usize
+    }
+
+    fn membership_copy(&self) -> Box<dyn ConsistentHasher>{
+        let mut copy = Self::new(self.k(), self.probes());
+        for node in self.nodes(){
+            MultiProbeHash::add_node(&mut copy, node).expect("members are distinct");
+        }
+        Box::new(copy)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    // the same script for every scheme: five nodes, then `joining` comes and goes
+    fn exercise<H: ConsistentHasher>(mut hasher: H, nodes: [u64; 5], joining: u64){
+        assert_eq!(None, hasher.locate(1));
+        for node in nodes{
+            hasher.add_node(node).unwrap();
+        }
+        assert_eq!(nodes.to_vec(), hasher.members());
+        assert_eq!(Err(RingError::DuplicateNode(nodes[0])), hasher.add_node(nodes[0]));
+        assert_eq!(Err(RingError::UnknownNode(joining)), hasher.plan_migration(&[], MembershipChange::Leave(joining)));
+
+        let keys = (0..2000u64).map(|i| i.wrapping_mul(0x9e3779b97f4a7c15) >> 44).collect::<Vec<u64>>();
+        for key in keys.iter().chain([u64::MAX].iter()){
+            assert!(nodes.contains(&hasher.locate(*key).unwrap()));
+        }
+
+        for change in [MembershipChange::Join(joining), MembershipChange::Leave(joining)]{
+            let before = keys.iter().map(|key| hasher.locate(*key).unwrap()).collect::<Vec<u64>>();
+            let plan = hasher.plan_migration(&keys, change).unwrap();
+            assert!(!plan.is_empty());
+            match change{
+                MembershipChange::Join(node) => hasher.add_node(node).unwrap(),
+                MembershipChange::Leave(node) => hasher.remove_node(node).unwrap(),
+            }
+            let actual = keys.iter().zip(before.iter())
+                .filter(|(key, from)| hasher.locate(**key) != Some(**from))
+                .map(|(key, from)| ResourceMove { key: *key, from: *from, to: hasher.locate(*key).unwrap() })
+                .collect::<Vec<ResourceMove>>();
+            assert_eq!(actual, plan);
+        }
+        assert_eq!(Err(RingError::UnknownNode(joining)), hasher.remove_node(joining));
+        assert_eq!(nodes.to_vec(), hasher.members());
+    }
+
+    #[test]
+    fn test_every_scheme(){
+        // spread over the 20-bit space, since multi-probe uses ids as tokens
+        let nodes = [100000, 300000, 500000, 700000, 900000];
+        exercise(c02_ringhash_1::HashRing::with_vnodes(20, 16), nodes, 400000);
+        exercise(c02_ringhash_2::HashRing::with_vnodes(20, 16), nodes, 400000);
+        exercise(RendezvousHash::new(), nodes, 400000);
+        exercise(Maglev::new(4099), nodes, 400000);
+        exercise(MultiProbeHash::new(20, 21), nodes, 400000);
+        // jump hash buckets are numbered from 0 and grow at the tail
+        exercise(JumpHash::new(0), [0, 1, 2, 3, 4], 5);
+        assert_eq!(Err(RingError::OutOfRange(7)), ConsistentHasher::add_node(&mut JumpHash::new(5), 7));
+        assert_eq!(Err(RingError::OutOfRange(2)), ConsistentHasher::remove_node(&mut JumpHash::new(5), 2));
+    }
+
+    #[test]
+    fn test_switch_scheme_at_runtime(){
+        let mut schemes: Vec<Box<dyn ConsistentHasher>> = vec![
+            Box::new(c02_ringhash_2::HashRing::with_vnodes(20, 16)),
+            Box::new(RendezvousHash::new()),
+            Box::new(Maglev::new(4099)),
+        ];
+        for scheme in schemes.iter_mut(){
+            scheme.add_node(10).unwrap();
+            scheme.add_node(20).unwrap();
+            assert_eq!(vec![10, 20], scheme.members());
+            let plan = scheme.plan_migration(&[12345], MembershipChange::Leave(10)).unwrap();
+            scheme.remove_node(10).unwrap();
+            assert_eq!(Some(20), scheme.locate(12345));
+            assert!(plan.iter().all(|step| step.from == 10 && step.to == 20));
+        }
+    }
+}
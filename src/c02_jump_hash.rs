@@ -0,0 +1,131 @@
+use std::hash::Hash;
+
+use crate::c02_key_hash::KeyHasher;
+
+/// Lamping & Veach, "A Fast, Minimal Memory, Consistent Hash Algorithm".
+/// Maps `key` to a bucket in `0..num_buckets` without any lookup table.
+/// Panics if `num_buckets` is 0.
+pub fn jump_consistent_hash(key: u64, num_buckets: u32) -> u32{
+    assert!(num_buckets > 0, "No bucket to place the key in");
+    let mut key = key;
+    let mut b: i64 = -1;
+    let mut j: i64 = 0;
+    while j < num_buckets as i64{
+        b = j;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b as u32
+}
+
+/// Jump consistent hash over buckets numbered `0..num_buckets`.
+///
+/// Buckets can only be added or removed at the tail, which is what makes the
+/// table-free lookup possible. Use `HashRing` when arbitrary nodes must leave.
+pub struct JumpHash{
+    num_buckets: u32,
+    hasher: KeyHasher,
+}
+
+impl JumpHash{
+    pub fn new(num_buckets: u32) -> Self{
+        Self::with_seed(num_buckets, 0)
+    }
+
+    pub fn with_seed(num_buckets: u32, seed: u32) -> Self{
+        Self { num_buckets, hasher: KeyHasher::new(64, seed) }
+    }
+
+    pub fn seed(&self) -> u32{
+        self.hasher.seed()
+    }
+
+    pub fn num_buckets(&self) -> u32{
+        self.num_buckets
+    }
+
+    /// Appends a bucket and returns its number.
+    pub fn add_bucket(&mut self) -> u32{
+        self.num_buckets += 1;
+        self.num_buckets - 1
+    }
+
+    /// Drops the last bucket and returns its number, if there was one.
+    pub fn remove_bucket(&mut self) -> Option<u32>{
+        if self.num_buckets == 0{
+            return None;
+        }
+        self.num_buckets -= 1;
+        Some(self.num_buckets)
+    }
+
+    /// Bucket owning an already hashed key. Panics without buckets.
+    pub fn lookup_bucket(&self, hash_value: u64) -> u32{
+        jump_consistent_hash(hash_value, self.num_buckets)
+    }
+
+    /// Bucket responsible for `key`.
+    pub fn node_for<K: Hash + ?Sized>(&self, key: &K) -> u32{
+        self.lookup_bucket(self.hasher.hash(key))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_lookup_in_range(){
+        let jh = JumpHash::new(7);
+        for i in 0..1000u64{
+            assert!(jh.node_for(&i) < 7);
+        }
+        assert_eq!(0, JumpHash::new(1).node_for("anything"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_no_buckets(){
+        jump_consistent_hash(42, 0);
+    }
+
+    #[test]
+    fn test_only_one_nth_of_keys_move(){
+        let keys = (0..100000u64).map(|i| format!("key:{}", i)).collect::<Vec<String>>();
+        let mut jh = JumpHash::new(9);
+        let before = keys.iter().map(|key| jh.node_for(key)).collect::<Vec<u32>>();
+
+        let new_bucket = jh.add_bucket();
+        assert_eq!(9, new_bucket);
+        let mut moved = 0;
+        for (key, old_bucket) in keys.iter().zip(before.iter()){
+            let bucket = jh.node_for(key);
+            if bucket != *old_bucket{
+                // keys only ever move to the new bucket
+                assert_eq!(new_bucket, bucket);
+                moved += 1;
+            }
+        }
+        // about 1/10 of the keys move
+        let fraction = moved as f64 / keys.len() as f64;
+        assert!((fraction - 0.1).abs() < 0.01, "moved fraction {}", fraction);
+
+        // removing the tail bucket restores the old placement
+        assert_eq!(Some(9), jh.remove_bucket());
+        for (key, old_bucket) in keys.iter().zip(before.iter()){
+            assert_eq!(*old_bucket, jh.node_for(key));
+        }
+    }
+
+    #[test]
+    fn test_balance(){
+        let jh = JumpHash::new(10);
+        let mut load = [0u32; 10];
+        for i in 0..100000u64{
+            load[jh.node_for(&i) as usize] += 1;
+        }
+        for count in load{
+            assert!((9000..11000).contains(&count), "bucket load {}", count);
+        }
+    }
+}
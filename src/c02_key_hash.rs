@@ -0,0 +1,119 @@
+use std::hash::{Hash, Hasher};
+
+/// Collects the bytes a `Hash` impl feeds in, so that murmur3 can hash them
+/// in one go instead of relying on `DefaultHasher`, whose algorithm may change.
+/// Integers are written little-endian and `usize`s (including length
+/// prefixes) as `u64`, so the bytes are the same on every platform. The
+/// `Hash` impls themselves are not guaranteed to stay the same across Rust
+/// releases; keys that need fixed positions go through `hash_bytes`.
+struct ByteCollector{
+    bytes: Vec<u8>,
+}
+
+impl Hasher for ByteCollector{
+    fn write(&mut self, bytes: &[u8]){
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn write_u16(&mut self, i: u16){
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32){
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64){
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128){
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize){
+        self.write_u64(i as u64);
+    }
+
+    fn finish(&self) -> u64{
+        // never called, KeyHasher reads the collected bytes directly
+        0
+    }
+}
+
+/// Largest position of a `k`-bit hash space. Panics unless `k` is between 1
+/// and 64, as every ring constructor goes through here.
+pub fn space_max(k: u32) -> u64{
+    if !(1..=64).contains(&k){
+        panic!("Hash space width k must be between 1 and 64, got {}", k);
+    }
+    u64::MAX >> (64 - k)
+}
+
+/// Hashes arbitrary keys into a `k`-bit ring space with murmur3.
+#[derive(Debug, Clone, Copy)]
+pub struct KeyHasher{
+    seed: u32,
+    max: u64,
+}
+
+impl KeyHasher{
+    pub fn new(k: u32, seed: u32) -> Self{
+        Self { seed, max: space_max(k) }
+    }
+
+    pub fn seed(&self) -> u32{
+        self.seed
+    }
+
+    /// Hashes `bytes` as they are: the same position on every platform and
+    /// Rust release.
+    pub fn hash_bytes(&self, bytes: &[u8]) -> u64{
+        let hash = murmur3::murmur3_x64_128(&mut std::io::Cursor::new(bytes), self.seed).unwrap() as u64;
+        hash & self.max
+    }
+
+    pub fn hash<K: Hash + ?Sized>(&self, key: &K) -> u64{
+        let mut collector = ByteCollector { bytes: vec![] };
+        key.hash(&mut collector);
+        self.hash_bytes(&collector.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_hash(){
+        let hasher = KeyHasher::new(10, 0);
+        assert_eq!(hasher.hash("host:8080"), hasher.hash(&"host:8080".to_string()));
+        assert_ne!(hasher.hash("host:8080"), hasher.hash("host:8081"));
+        for i in 0..1000u64{
+            assert!(hasher.hash(&i) <= 1023);
+        }
+
+        let other_seed = KeyHasher::new(10, 42);
+        assert_ne!(hasher.hash_bytes(b"key"), other_seed.hash_bytes(b"key"));
+    }
+
+    #[test]
+    fn test_space_max(){
+        assert_eq!(1, space_max(1));
+        assert_eq!(1023, space_max(10));
+        assert_eq!(u64::MAX, space_max(64));
+        for k in [0, 65]{
+            assert!(std::panic::catch_unwind(|| space_max(k)).is_err());
+            assert!(std::panic::catch_unwind(|| KeyHasher::new(k, 0)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_platform_independent_bytes(){
+        let hasher = KeyHasher::new(64, 0);
+        assert_eq!(hasher.hash_bytes(&0x0102u64.to_le_bytes()), hasher.hash(&0x0102u64));
+        assert_eq!(hasher.hash_bytes(&[7, 0, 0, 0, 1, 0, 0, 0]), hasher.hash(&(7u32, 1u32)));
+        // length prefixes are written as a u64 whatever the width of usize
+        assert_eq!(hasher.hash_bytes(&[2, 0, 0, 0, 0, 0, 0, 0, 1, 2]), hasher.hash(&[1u8, 2][..]));
+    }
+}
@@ -0,0 +1,177 @@
+use std::hash::Hash;
+
+use crate::c02_key_hash::KeyHasher;
+
+fn is_prime(n: usize) -> bool{
+    if n < 2{
+        return false;
+    }
+    let mut i = 2;
+    while i * i <= n{
+        if n.is_multiple_of(i){
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Maglev consistent hashing (Eisenbud et al., NSDI 2016).
+///
+/// Each backend walks its own permutation of a prime-sized lookup table and
+/// backends take turns claiming the next free slot, so every backend ends up
+/// with M / N entries give or take one. A lookup is a single table index.
+pub struct Maglev{
+    table_size: usize,
+    backends: Vec<u64>,
+    table: Vec<Option<u64>>,
+    hasher: KeyHasher,
+}
+
+impl Maglev{
+    /// `table_size` must be prime and should be much larger than the number
+    /// of backends (the paper uses 65537 for hundreds of backends).
+    pub fn new(table_size: usize) -> Self{
+        if !is_prime(table_size){
+            panic!("Maglev table size must be prime");
+        }
+        Self { table_size, backends: vec![], table: vec![None; table_size], hasher: KeyHasher::new(64, 0) }
+    }
+
+    pub fn backends(&self) -> &[u64]{
+        &self.backends
+    }
+
+    pub fn table(&self) -> &[Option<u64>]{
+        &self.table
+    }
+
+    /// Permutation of the table slots preferred by `backend`.
+    fn permutation(&self, backend: u64) -> impl Iterator<Item = usize>{
+        let table_size = self.table_size as u64;
+        let offset = self.hasher.hash(&(backend, 0u8)) % table_size;
+        let skip = self.hasher.hash(&(backend, 1u8)) % (table_size - 1) + 1;
+        (0..table_size).map(move |j| ((offset + j * skip) % table_size) as usize)
+    }
+
+    /// Refills the lookup table from scratch and returns how many entries
+    /// changed owner compared with the previous table.
+    pub fn rebuild(&mut self) -> usize{
+        let mut table = vec![None; self.table_size];
+        if !self.backends.is_empty(){
+            let mut permutations = self.backends.iter()
+                .map(|backend| (*backend, self.permutation(*backend)))
+                .collect::<Vec<(u64, _)>>();
+            let mut filled = 0;
+            'fill: loop{
+                for (backend, permutation) in permutations.iter_mut(){
+                    let slot = permutation.find(|slot| table[*slot].is_none()).unwrap();
+                    table[slot] = Some(*backend);
+                    filled += 1;
+                    if filled == self.table_size{
+                        break 'fill;
+                    }
+                }
+            }
+        }
+        let changed = table.iter().zip(self.table.iter()).filter(|(new, old)| new != old).count();
+        self.table = table;
+        changed
+    }
+
+    /// Adds `backend` and rebuilds the table. Returns the number of table
+    /// entries that changed owner.
+    pub fn add_backend(&mut self, backend: u64) -> usize{
+        if self.backends.contains(&backend){
+            return 0;
+        }
+        self.backends.push(backend);
+        // the fill order decides ties, keep it independent of insertion order
+        self.backends.sort();
+        self.rebuild()
+    }
+
+    /// Removes `backend` and rebuilds the table. Returns the number of table
+    /// entries that changed owner.
+    pub fn remove_backend(&mut self, backend: u64) -> usize{
+        let before = self.backends.len();
+        self.backends.retain(|b| *b != backend);
+        if self.backends.len() == before{
+            return 0;
+        }
+        self.rebuild()
+    }
+
+    pub fn lookup_backend(&self, hash_value: u64) -> Option<u64>{
+        self.table[(hash_value % self.table_size as u64) as usize]
+    }
+
+    pub fn node_for<K: Hash + ?Sized>(&self, key: &K) -> Option<u64>{
+        self.lookup_backend(self.hasher.hash(key))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::{cell::RefCell, collections::HashMap};
+    use crate::c02_ringhash_2::{HashRing, Node};
+
+    #[test]
+    #[should_panic]
+    fn test_table_size_must_be_prime(){
+        Maglev::new(65536);
+    }
+
+    #[test]
+    fn test_balance(){
+        let mut maglev = Maglev::new(65537);
+        for backend in 1..=10{
+            maglev.add_backend(backend);
+        }
+        let mut entries = HashMap::new();
+        for backend in maglev.table().iter(){
+            *entries.entry(backend.unwrap()).or_insert(0) += 1;
+        }
+        let max = *entries.values().max().unwrap();
+        let min = *entries.values().min().unwrap();
+        assert!(max - min <= 1, "entries per backend between {} and {}", min, max);
+    }
+
+    #[test]
+    fn test_disruption_compared_with_hash_ring(){
+        let hasher = KeyHasher::new(20, 1);
+        let backends = (1..=10u64).map(|i| hasher.hash(&i)).collect::<Vec<u64>>();
+        let joining = hasher.hash(&11u64);
+
+        let mut maglev = Maglev::new(65537);
+        let mut hr = HashRing::new(20);
+        for backend in backends.iter(){
+            maglev.add_backend(*backend);
+            hr.add_node(RefCell::new(Node::new(*backend)).into()).unwrap();
+        }
+        let keys = (0..20000u64).map(|i| KeyHasher::new(20, 0).hash(&i)).collect::<Vec<u64>>();
+        for key in keys.iter(){
+            hr.add_resource(*key).unwrap();
+        }
+        let before = keys.iter().map(|key| maglev.lookup_backend(*key)).collect::<Vec<Option<u64>>>();
+
+        let changed_entries = maglev.add_backend(joining);
+        let moved_keys = keys.iter().zip(before.iter())
+            .filter(|(key, old)| maglev.lookup_backend(**key) != **old)
+            .count();
+        let ring_moves = hr.add_node(RefCell::new(Node::new(joining)).into()).unwrap().len();
+        println!(
+            "table entries changed: {} / {}, keys moved: maglev {}, ring {} (of {})",
+            changed_entries, 65537, moved_keys, ring_moves, keys.len()
+        );
+
+        // ideal is 1/11 of the table; Maglev trades a little extra churn for balance
+        let fraction = changed_entries as f64 / 65537.0;
+        assert!(fraction > 1.0 / 11.0 - 0.01 && fraction < 0.15, "changed fraction {}", fraction);
+
+        assert_eq!(changed_entries, maglev.remove_backend(joining));
+        let restored = keys.iter().map(|key| maglev.lookup_backend(*key)).collect::<Vec<Option<u64>>>();
+        assert_eq!(before, restored);
+    }
+}
@@ -0,0 +1,14 @@
+/// A single node joining or leaving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MembershipChange{
+    Join(u64),
+    Leave(u64),
+}
+
+/// A resource handed from one node to another by a membership change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceMove{
+    pub key: u64,
+    pub from: u64,
+    pub to: u64,
+}
@@ -0,0 +1,142 @@
+use std::hash::Hash;
+
+use crate::c02_key_hash::KeyHasher;
+use crate::c02_ring_error::RingError;
+use crate::c02_ringhash_3::HashRing;
+
+/// Multi-probe consistent hashing (Appleton & O'Reilly, 2015).
+///
+/// Nodes keep a single token each. A key is hashed `probes` times and goes to
+/// the node whose token follows one of the probes most closely, measured with
+/// the ring's clockwise `distance`. 21 probes give a peak-to-mean load of
+/// about 1.05 without the memory of hundreds of virtual nodes.
+pub struct MultiProbeHash{
+    ring: HashRing,
+    k: u32,
+    probes: u32,
+    hasher: KeyHasher,
+}
+
+impl MultiProbeHash{
+    /// Panics if `probes` is 0, as no key could be placed.
+    pub fn new(k: u32, probes: u32) -> Self{
+        if probes == 0{
+            panic!("Multi-probe hashing needs at least one probe");
+        }
+        Self { ring: HashRing::new(k), k, probes, hasher: KeyHasher::new(k, 0) }
+    }
+
+    pub fn k(&self) -> u32{
+        self.k
+    }
+
+    pub fn probes(&self) -> u32{
+        self.probes
+    }
+
+    pub fn hash_key<K: Hash + ?Sized>(&self, key: &K) -> u64{
+        self.hasher.hash(key)
+    }
+
+    pub fn add_node(&mut self, hash_value: u64) -> Result<(), RingError>{
+        self.ring.add_node(hash_value)
+    }
+
+    /// Places a node named e.g. "host:port" and returns its token.
+    pub fn add_named_node(&mut self, name: &str) -> Result<u64, RingError>{
+        let token = self.hash_key(name);
+        self.add_node(token)?;
+        Ok(token)
+    }
+
+    pub fn remove_node(&mut self, hash_value: u64) -> Result<(), RingError>{
+        self.ring.remove_node(hash_value).map(|_| ())
+    }
+
+    pub fn nodes(&self) -> Vec<u64>{
+        self.ring.nodes().map(|node| node.hash_value()).collect()
+    }
+
+    /// Node owning an already hashed key, or `None` on an empty ring.
+    pub fn lookup_node(&self, hash_value: u64) -> Option<u64>{
+        if self.ring.is_empty(){
+            return None;
+        }
+        (0..self.probes)
+            .map(|i| {
+                let probe = self.hasher.hash(&(hash_value, i));
+                // probes are hashed into the ring's range and the ring is not empty
+                let node = self.ring.lookup_node(probe).unwrap().hash_value();
+                (self.ring.distance(probe, node), node)
+            })
+            .min()
+            .map(|(_, node)| node)
+    }
+
+    pub fn node_for<K: Hash + ?Sized>(&self, key: &K) -> Option<u64>{
+        self.lookup_node(self.hash_key(key))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::{cell::RefCell, collections::HashMap};
+    use crate::c02_ringhash_2::{self, Node, NodeRefExt};
+
+    // peak load divided by mean load over `nodes` nodes
+    fn peak_to_mean(owners: impl Iterator<Item = u64>, nodes: usize) -> f64{
+        let mut load = HashMap::new();
+        let mut total = 0;
+        for owner in owners{
+            *load.entry(owner).or_insert(0) += 1;
+            total += 1;
+        }
+        *load.values().max().unwrap() as f64 / (total as f64 / nodes as f64)
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_needs_a_probe(){
+        MultiProbeHash::new(16, 0);
+    }
+
+    #[test]
+    fn test_balance_compared_with_hash_ring(){
+        let mut mp = MultiProbeHash::new(32, 21);
+        let mut hr: c02_ringhash_2::HashRing = c02_ringhash_2::HashRing::new(32);
+        for i in 0..16{
+            let token = mp.add_named_node(&format!("10.0.0.{}:7000", i)).unwrap();
+            hr.add_node(RefCell::new(Node::new(token)).into()).unwrap();
+        }
+        let keys = (0..50000u64).map(|i| mp.hash_key(&i)).collect::<Vec<u64>>();
+
+        let multi_probe = peak_to_mean(keys.iter().map(|key| mp.lookup_node(*key).unwrap()), 16);
+        let ring = peak_to_mean(keys.iter().map(|key| hr.chord_lookup(*key).unwrap().hash_value()), 16);
+        println!("peak-to-mean load: multi-probe {:.3}, ring {:.3}", multi_probe, ring);
+        assert!(multi_probe < ring);
+        assert!(multi_probe < 1.2);
+    }
+
+    #[test]
+    fn test_membership_change(){
+        let mut mp = MultiProbeHash::new(16, 21);
+        assert_eq!(None, mp.node_for("key"));
+        for token in [1000, 20000, 40000, 60000]{
+            mp.add_node(token).unwrap();
+        }
+        let keys = (0..2000u64).map(|i| mp.hash_key(&i)).collect::<Vec<u64>>();
+        let before = keys.iter().map(|key| mp.lookup_node(*key).unwrap()).collect::<Vec<u64>>();
+
+        // keys only move to the joining node, and back when it leaves
+        mp.add_node(30000).unwrap();
+        for (key, owner) in keys.iter().zip(before.iter()){
+            let now = mp.lookup_node(*key).unwrap();
+            assert!(now == *owner || now == 30000);
+        }
+        assert!(mp.remove_node(30000).is_ok());
+        assert_eq!(Err(RingError::UnknownNode(30000)), mp.remove_node(30000));
+        let after = keys.iter().map(|key| mp.lookup_node(*key).unwrap()).collect::<Vec<u64>>();
+        assert_eq!(before, after);
+    }
+}
@@ -0,0 +1,265 @@
+use std::{collections::HashMap, hash::Hash};
+
+use crate::c02_key_hash::KeyHasher;
+use crate::c02_ring_error::RingError;
+use crate::c02_migration::ResourceMove;
+
+/// Rendezvous (highest random weight) hashing.
+///
+/// Every node scores every key and the key goes to the highest score. With
+/// weights the score is `-weight / ln(u)` where `u` is hash(node, key) mapped
+/// into (0, 1), so a node of weight 2 receives twice the share of a node of
+/// weight 1 (Schindelhauer & Schomaker's weighted variant).
+pub struct RendezvousHash{
+    weights: HashMap<u64, f64>,
+    resources: HashMap<u64, HashMap<u64, u64>>, // node -> resources it owns
+    hasher: KeyHasher,
+}
+
+impl RendezvousHash{
+    pub fn new() -> Self{
+        Self::with_seed(0)
+    }
+
+    pub fn with_seed(seed: u32) -> Self{
+        Self { weights: HashMap::new(), resources: HashMap::new(), hasher: KeyHasher::new(64, seed) }
+    }
+
+    pub fn seed(&self) -> u32{
+        self.hasher.seed()
+    }
+
+    /// Weight of `node`, 0 if it is not a member.
+    pub fn weight(&self, node: u64) -> f64{
+        self.weights.get(&node).copied().unwrap_or(0.0)
+    }
+
+    pub fn hash_key<K: Hash + ?Sized>(&self, key: &K) -> u64{
+        self.hasher.hash(key)
+    }
+
+    fn score(&self, node: u64, weight: f64, hash_value: u64) -> f64{
+        // top 53 bits as a float strictly inside (0, 1)
+        let u = ((self.hasher.hash(&(node, hash_value)) >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+        -weight / u.ln()
+    }
+
+    /// Nodes ordered by score for `hash_value`, best first.
+    fn ranking(&self, hash_value: u64) -> Vec<u64>{
+        let mut scored = self.weights.iter()
+            .map(|(node, weight)| (self.score(*node, *weight, hash_value), *node))
+            .collect::<Vec<(f64, u64)>>();
+        // ties broken by node id so the order does not depend on HashMap order
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+        scored.into_iter().map(|(_, node)| node).collect()
+    }
+
+    pub fn lookup_node(&self, hash_value: u64) -> Option<u64>{
+        self.ranking(hash_value).first().copied()
+    }
+
+    /// The `n` highest scoring nodes for `hash_value`, used as replica set.
+    pub fn top_n(&self, hash_value: u64, n: usize) -> Vec<u64>{
+        let mut ranking = self.ranking(hash_value);
+        ranking.truncate(n);
+        ranking
+    }
+
+    pub fn node_for<K: Hash + ?Sized>(&self, key: &K) -> Option<u64>{
+        self.lookup_node(self.hash_key(key))
+    }
+
+    pub fn nodes(&self) -> Vec<u64>{
+        let mut nodes = self.weights.keys().copied().collect::<Vec<u64>>();
+        nodes.sort();
+        nodes
+    }
+
+    pub fn resources(&self, node: u64) -> HashMap<u64, u64>{
+        self.resources.get(&node).cloned().unwrap_or_default()
+    }
+
+    pub fn add_resource(&mut self, hash_value: u64) -> Result<(), RingError>{
+        let node = self.lookup_node(hash_value).ok_or(RingError::EmptyRing)?;
+        self.resources.entry(node).or_default().insert(hash_value, hash_value);
+        Ok(())
+    }
+
+    /// Adds `node` with `weight` and moves over every resource it now wins.
+    /// Re-adding a member changes its weight, so keys may also move away
+    /// from it when the weight drops.
+    pub fn add_node(&mut self, node: u64, weight: f64) -> Result<Vec<ResourceMove>, RingError>{
+        if !(weight.is_finite() && weight > 0.0){
+            return Err(RingError::InvalidWeight(node));
+        }
+        let reweighted = self.weights.insert(node, weight).is_some();
+        self.resources.entry(node).or_default();
+
+        let mut moves = vec![];
+        for (owner, resources) in self.resources.iter(){
+            if *owner == node && !reweighted{
+                continue;
+            }
+            for key in resources.keys(){
+                let to = self.lookup_node(*key).expect("node is a member");
+                if to != *owner{
+                    moves.push(ResourceMove { key: *key, from: *owner, to });
+                }
+            }
+        }
+        self.apply(&moves);
+        Ok(moves)
+    }
+
+    /// Removes `node` and hands each of its resources to the runner-up. The
+    /// last member cannot leave while it holds resources, as they would have
+    /// nowhere to go.
+    pub fn remove_node(&mut self, node: u64) -> Result<Vec<ResourceMove>, RingError>{
+        if !self.weights.contains_key(&node){
+            return Err(RingError::UnknownNode(node));
+        }
+        if self.weights.len() == 1 && !self.resources(node).is_empty(){
+            return Err(RingError::EmptyRing);
+        }
+        self.weights.remove(&node);
+        let orphaned = self.resources.remove(&node).unwrap_or_default();
+        let mut moves = vec![];
+        for (key, value) in orphaned{
+            let to = self.lookup_node(key).expect("another member is left");
+            self.resources.entry(to).or_default().insert(key, value);
+            moves.push(ResourceMove { key, from: node, to });
+        }
+        Ok(moves)
+    }
+
+    fn apply(&mut self, moves: &[ResourceMove]){
+        for resource_move in moves{
+            let value = self.resources.get_mut(&resource_move.from).unwrap().remove(&resource_move.key).unwrap();
+            self.resources.entry(resource_move.to).or_default().insert(resource_move.key, value);
+        }
+    }
+}
+
+impl Default for RendezvousHash{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::cell::RefCell;
+    use crate::c02_ringhash_2::{HashRing, Node};
+
+    #[test]
+    fn test_weighted_share(){
+        let mut rh = RendezvousHash::new();
+        rh.add_node(1, 1.0).unwrap();
+        rh.add_node(2, 3.0).unwrap();
+        let mut heavy = 0;
+        for i in 0..20000u64{
+            if rh.node_for(&i) == Some(2){
+                heavy += 1;
+            }
+        }
+        let share = heavy as f64 / 20000.0;
+        assert!((share - 0.75).abs() < 0.02, "share {}", share);
+    }
+
+    #[test]
+    fn test_reweight(){
+        let mut rh = RendezvousHash::new();
+        rh.add_node(1, 1.0).unwrap();
+        rh.add_node(2, 1.0).unwrap();
+        for i in 0..2000u64{
+            rh.add_resource(rh.hash_key(&i)).unwrap();
+        }
+        let held = rh.resources(2).len();
+
+        // a lighter node 2 hands keys back to node 1
+        let moves = rh.add_node(2, 0.25).unwrap();
+        assert!(!moves.is_empty());
+        assert!(moves.iter().all(|m| m.from == 2 && m.to == 1));
+        assert_eq!(held - moves.len(), rh.resources(2).len());
+        for node in rh.nodes(){
+            for key in rh.resources(node).keys(){
+                assert_eq!(Some(node), rh.lookup_node(*key));
+            }
+        }
+
+        for weight in [0.0, -1.0, f64::NAN, f64::INFINITY]{
+            assert_eq!(Err(RingError::InvalidWeight(3)), rh.add_node(3, weight));
+        }
+        assert_eq!(vec![1, 2], rh.nodes());
+        assert_eq!(0.25, rh.weight(2));
+    }
+
+    #[test]
+    fn test_top_n(){
+        let mut rh = RendezvousHash::new();
+        for node in 1..=5{
+            rh.add_node(node, 1.0).unwrap();
+        }
+        for i in 0..100u64{
+            let hash_value = rh.hash_key(&i);
+            let replicas = rh.top_n(hash_value, 3);
+            assert_eq!(3, replicas.len());
+            assert_eq!(rh.lookup_node(hash_value), Some(replicas[0]));
+            let mut distinct = replicas.clone();
+            distinct.sort();
+            distinct.dedup();
+            assert_eq!(3, distinct.len());
+        }
+        assert_eq!(5, rh.top_n(0, 10).len());
+    }
+
+    #[test]
+    fn test_migration_compared_with_hash_ring(){
+        let keys = (0..10000u64).map(|i| KeyHasher::new(20, 0).hash(&i)).collect::<Vec<u64>>();
+        let nodes = (1..=10u64).map(|i| KeyHasher::new(20, 1).hash(&i)).collect::<Vec<u64>>();
+        let joining = KeyHasher::new(20, 1).hash(&11u64);
+
+        let mut hr = HashRing::new(20);
+        let mut rh = RendezvousHash::new();
+        for node in nodes.iter(){
+            hr.add_node(RefCell::new(Node::new(*node)).into()).unwrap();
+            rh.add_node(*node, 1.0).unwrap();
+        }
+        for key in keys.iter(){
+            hr.add_resource(*key).unwrap();
+            rh.add_resource(*key).unwrap();
+        }
+
+        let ring_moves = hr.add_node(RefCell::new(Node::new(joining)).into()).unwrap();
+        let rendezvous_moves = rh.add_node(joining, 1.0).unwrap();
+        println!("keys moved: ring {}, rendezvous {}", ring_moves.len(), rendezvous_moves.len());
+        assert!(ring_moves.iter().all(|m| m.to == joining));
+        assert!(rendezvous_moves.iter().all(|m| m.to == joining));
+        assert_eq!(rendezvous_moves.len(), rh.resources(joining).len());
+        // about 1/11 of the keys move to the new node
+        let fraction = rendezvous_moves.len() as f64 / keys.len() as f64;
+        assert!((fraction - 1.0 / 11.0).abs() < 0.02, "moved fraction {}", fraction);
+
+        // leaving gives back exactly what the node holds
+        let leaving = rh.remove_node(joining).unwrap();
+        assert_eq!(rendezvous_moves.len(), leaving.len());
+        assert!(leaving.iter().all(|m| m.from == joining && m.to != joining));
+    }
+
+    #[test]
+    fn test_refused_changes(){
+        let mut rh = RendezvousHash::new();
+        assert_eq!(Err(RingError::EmptyRing), rh.add_resource(7));
+        assert_eq!(Err(RingError::UnknownNode(1)), rh.remove_node(1));
+        rh.add_node(1, 1.0).unwrap();
+        rh.add_node(2, 1.0).unwrap();
+        rh.add_resource(7).unwrap();
+        let owner = rh.lookup_node(7).unwrap();
+        let other = 3 - owner;
+        assert_eq!(Ok(vec![]), rh.remove_node(other));
+        // the last member keeps its resources rather than dropping them
+        assert_eq!(Err(RingError::EmptyRing), rh.remove_node(owner));
+        assert_eq!(1, rh.resources(owner).len());
+    }
+}
@@ -0,0 +1,158 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use crate::c02_ring_error::RingError;
+use crate::{c02_ringhash_2, c02_ringhash_3};
+use crate::c02_ringhash_2::NodeRefExt;
+
+/// Token-level operations shared by the ring implementations, so the same
+/// scenario can be checked against the linked-list ring and the sorted one.
+pub trait RingBackend{
+    fn add_token(&mut self, hash_value: u64) -> Result<(), RingError>;
+
+    fn remove_token(&mut self, hash_value: u64) -> Result<(), RingError>;
+
+    /// Token of the node owning `hash_value`.
+    fn lookup_token(&self, hash_value: u64) -> Result<u64, RingError>;
+
+    fn add_key(&mut self, hash_value: u64) -> Result<(), RingError>;
+
+    /// Keys stored on `token`, empty if there is no node at `token`.
+    fn keys_on(&self, token: u64) -> Vec<u64>;
+
+    /// All tokens in ascending order.
+    fn tokens(&self) -> Vec<u64>;
+}
+
+fn sorted_keys(resources: &HashMap<u64, u64>) -> Vec<u64>{
+    let mut keys = resources.keys().copied().collect::<Vec<u64>>();
+    keys.sort();
+    keys
+}
+
+impl RingBackend for c02_ringhash_2::HashRing{
+    fn add_token(&mut self, hash_value: u64) -> Result<(), RingError>{
+        self.add_node(RefCell::new(c02_ringhash_2::Node::new(hash_value)).into()).map(|_| ())
+    }
+
+    fn remove_token(&mut self, hash_value: u64) -> Result<(), RingError>{
+        self.remove_node(hash_value).map(|_| ())
+    }
+
+    fn lookup_token(&self, hash_value: u64) -> Result<u64, RingError>{
+        self.chord_lookup(hash_value).map(|node| node.hash_value())
+    }
+
+    fn add_key(&mut self, hash_value: u64) -> Result<(), RingError>{
+        self.add_resource(hash_value)
+    }
+
+    fn keys_on(&self, token: u64) -> Vec<u64>{
+        match self.chord_lookup(token){
+            Ok(node) if node.hash_value() == token => sorted_keys(&node.resources()),
+            _ => vec![],
+        }
+    }
+
+    fn tokens(&self) -> Vec<u64>{
+        self.nodes().iter().map(|node| node.hash_value()).collect()
+    }
+}
+
+impl RingBackend for c02_ringhash_3::HashRing{
+    fn add_token(&mut self, hash_value: u64) -> Result<(), RingError>{
+        self.add_node(hash_value)
+    }
+
+    fn remove_token(&mut self, hash_value: u64) -> Result<(), RingError>{
+        self.remove_node(hash_value).map(|_| ())
+    }
+
+    fn lookup_token(&self, hash_value: u64) -> Result<u64, RingError>{
+        self.lookup_node(hash_value).map(|node| node.hash_value())
+    }
+
+    fn add_key(&mut self, hash_value: u64) -> Result<(), RingError>{
+        self.add_resource(hash_value)
+    }
+
+    fn keys_on(&self, token: u64) -> Vec<u64>{
+        match self.lookup_node(token){
+            Ok(node) if node.hash_value() == token => sorted_keys(node.resources()),
+            _ => vec![],
+        }
+    }
+
+    fn tokens(&self) -> Vec<u64>{
+        self.nodes().map(|node| node.hash_value()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn scenario<R: RingBackend>(ring: &mut R){
+        assert_eq!(Err(RingError::EmptyRing), ring.lookup_token(3));
+        ring.add_token(12).unwrap();
+        ring.add_token(18).unwrap();
+        assert_eq!(Err(RingError::DuplicateNode(12)), ring.add_token(12));
+        for hash_value in [24, 21, 16, 23, 2, 29, 28, 7, 10]{
+            ring.add_key(hash_value).unwrap();
+        }
+        assert_eq!(Err(RingError::OutOfRange(32)), ring.add_key(32));
+        ring.add_token(5).unwrap();
+        ring.add_token(27).unwrap();
+        ring.add_token(30).unwrap();
+        assert_eq!(vec![5, 12, 18, 27, 30], ring.tokens());
+        assert_eq!(vec![2], ring.keys_on(5));
+        assert_eq!(vec![7, 10], ring.keys_on(12));
+        assert_eq!(vec![16], ring.keys_on(18));
+        assert_eq!(vec![21, 23, 24], ring.keys_on(27));
+        assert_eq!(vec![28, 29], ring.keys_on(30));
+        assert_eq!(Ok(5), ring.lookup_token(31));
+        assert_eq!(Ok(27), ring.lookup_token(27));
+
+        assert_eq!(Ok(()), ring.remove_token(27));
+        assert_eq!(Err(RingError::UnknownNode(27)), ring.remove_token(27));
+        assert_eq!(vec![21, 23, 24, 28, 29], ring.keys_on(30));
+        assert_eq!(Ok(()), ring.remove_token(5));
+        assert_eq!(Ok(12), ring.lookup_token(2));
+        assert_eq!(vec![2, 7, 10], ring.keys_on(12));
+    }
+
+    #[test]
+    fn test_backends_agree(){
+        scenario(&mut c02_ringhash_2::HashRing::new(5));
+        scenario(&mut c02_ringhash_3::HashRing::new(5));
+    }
+
+    #[test]
+    fn test_backends_agree_on_random_ring(){
+        let mut linked = c02_ringhash_2::HashRing::new(16);
+        let mut sorted = c02_ringhash_3::HashRing::new(16);
+        let mut rng = StdRng::seed_from_u64(200);
+        let mut tokens = std::collections::HashSet::new();
+        while tokens.len() < 200{
+            let hash_value = rng.random::<u64>() % 65536;
+            if tokens.insert(hash_value){
+                linked.add_token(hash_value).unwrap();
+                sorted.add_token(hash_value).unwrap();
+            }
+        }
+        for _ in 0..2000{
+            let hash_value = rng.random::<u64>() % 65536;
+            linked.add_key(hash_value).unwrap();
+            sorted.add_key(hash_value).unwrap();
+            assert_eq!(linked.lookup_token(hash_value), sorted.lookup_token(hash_value));
+        }
+        for token in sorted.tokens().into_iter().step_by(3){
+            linked.remove_token(token).unwrap();
+            sorted.remove_token(token).unwrap();
+        }
+        assert_eq!(linked.tokens(), sorted.tokens());
+        for token in sorted.tokens(){
+            assert_eq!(linked.keys_on(token), sorted.keys_on(token));
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use std::{error::Error, fmt};
+
+/// Why a ring operation was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RingError{
+    /// The hash value does not fit in the ring's `k` bits.
+    OutOfRange(u64),
+    /// The operation needs at least one node on the ring.
+    EmptyRing,
+    /// A node already sits at this position, or a physical node with this id
+    /// is already on the ring.
+    DuplicateNode(u64),
+    /// No node sits at this position, or no physical node has this id.
+    UnknownNode(u64),
+    /// A migration plan no longer matches the ring it is applied to.
+    StalePlan,
+    /// `weight * vnodes_per_weight` tokens for this weight overflow a `u32`.
+    TooManyTokens(u32),
+    /// The weight given for this node is not a positive, finite number.
+    InvalidWeight(u64),
+}
+
+impl fmt::Display for RingError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            RingError::OutOfRange(hash_value) => write!(f, "hash value {} is out of range", hash_value),
+            RingError::EmptyRing => write!(f, "the ring has no nodes"),
+            RingError::DuplicateNode(hash_value) => write!(f, "a node already sits at {}", hash_value),
+            RingError::UnknownNode(hash_value) => write!(f, "no node at {}", hash_value),
+            RingError::StalePlan => write!(f, "the migration plan is out of date"),
+            RingError::TooManyTokens(weight) => write!(f, "weight {} needs more tokens than a u32 can count", weight),
+            RingError::InvalidWeight(node) => write!(f, "node {} needs a positive, finite weight", node),
+        }
+    }
+}
+
+impl Error for RingError{}
@@ -0,0 +1,41 @@
+/// Callbacks a `HashRing` fires as its membership and contents change, so
+/// layers on top (caches, metrics) can react. Every method defaults to doing
+/// nothing; implement only the events you care about.
+pub trait RingListener{
+    fn on_node_joined(&mut self, _hash_value: u64){}
+
+    fn on_node_left(&mut self, _hash_value: u64){}
+
+    /// A new resource was stored on `node`.
+    fn on_resource_added(&mut self, _hash_value: u64, _node: u64){}
+
+    /// `key` changed owner because of a membership change.
+    fn on_resource_moved(&mut self, _key: u64, _from: u64, _to: u64){}
+
+    fn on_finger_table_rebuilt(&mut self){}
+}
+
+/// Prints every event to stdout, the way the rings used to report progress.
+pub struct LoggingListener;
+
+impl RingListener for LoggingListener{
+    fn on_node_joined(&mut self, hash_value: u64){
+        println!("Added node with hash value {}", hash_value);
+    }
+
+    fn on_node_left(&mut self, hash_value: u64){
+        println!("Removed node with hash value {}", hash_value);
+    }
+
+    fn on_resource_added(&mut self, hash_value: u64, node: u64){
+        println!("Added resource with hash value {} to node {}", hash_value, node);
+    }
+
+    fn on_resource_moved(&mut self, key: u64, from: u64, to: u64){
+        println!("Moved resource with hash value {} from node {} to node {}", key, from, to);
+    }
+
+    fn on_finger_table_rebuilt(&mut self){
+        println!("Rebuilt finger tables");
+    }
+}
@@ -0,0 +1,228 @@
+use crate::c02_ring_error::RingError;
+use crate::c02_ringhash_2::CollisionPolicy;
+
+/// Bumped whenever the layout below changes.
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+// version 1 had no zone/rack labels; it is still read, with empty labels
+const UNLABELLED_VERSION: u32 = 1;
+// version 2 had no standalone flags or collision policy; it is still read,
+// as virtual nodes on a ring that rejects collisions
+const UNFLAGGED_VERSION: u32 = 2;
+
+const MAGIC: &[u8; 4] = b"RING";
+
+/// State of one token on the ring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeSnapshot{
+    pub hash_value: u64,
+    pub physical_id: u64,
+    /// Made by `Node::new` rather than as one of a physical node's tokens.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub standalone: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub zone: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub rack: String,
+    pub resources: Vec<(u64, u64)>, // sorted by key
+    pub fingers: Vec<(u64, u64)>,   // range -> hash value of the finger, sorted by range
+}
+
+/// Everything needed to rebuild a `HashRing` with identical lookups. Replica
+/// copies are derived state and are recomputed on restore.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RingSnapshot{
+    pub version: u32,
+    pub k: u32,
+    pub vnodes_per_weight: u32,
+    pub seed: u32,
+    pub replication_factor: u64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub collision_policy: CollisionPolicy,
+    pub nodes: Vec<NodeSnapshot>, // in ring order from the head
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError{
+    /// The data does not start with the snapshot magic bytes.
+    NotASnapshot,
+    /// Written by a version this code does not understand.
+    UnsupportedVersion(u32),
+    /// The data ended before the snapshot did.
+    Truncated,
+    /// There are bytes after the end of the snapshot.
+    TrailingBytes,
+    /// The hash space width is not between 1 and 64 bits.
+    InvalidK(u32),
+    /// A zone or rack label is not valid UTF-8.
+    InvalidLabel,
+    /// The collision policy code is not one this code knows.
+    UnknownPolicy(u32),
+    /// A finger points at a token that is not in the snapshot.
+    UnknownFinger(u64),
+    /// The ring refused a node from the snapshot, e.g. a duplicate token.
+    Ring(RingError),
+    /// A resource is recorded on a node that does not own its key.
+    MisplacedResource(u64),
+    #[cfg(feature = "serde")]
+    Json(String),
+}
+
+impl RingSnapshot{
+    /// Compact little-endian encoding: magic, version, header fields, then
+    /// every node with its standalone flag, labels, resources and fingers as
+    /// length-prefixed lists.
+    pub fn to_bytes(&self) -> Vec<u8>{
+        let mut bytes = MAGIC.to_vec();
+        for field in [self.version, self.k, self.vnodes_per_weight, self.seed]{
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.replication_factor.to_le_bytes());
+        bytes.extend_from_slice(&policy_code(self.collision_policy).to_le_bytes());
+        bytes.extend_from_slice(&(self.nodes.len() as u64).to_le_bytes());
+        for node in self.nodes.iter(){
+            bytes.extend_from_slice(&node.hash_value.to_le_bytes());
+            bytes.extend_from_slice(&node.physical_id.to_le_bytes());
+            bytes.push(node.standalone as u8);
+            for label in [&node.zone, &node.rack]{
+                bytes.extend_from_slice(&(label.len() as u64).to_le_bytes());
+                bytes.extend_from_slice(label.as_bytes());
+            }
+            for pairs in [&node.resources, &node.fingers]{
+                bytes.extend_from_slice(&(pairs.len() as u64).to_le_bytes());
+                for (a, b) in pairs.iter(){
+                    bytes.extend_from_slice(&a.to_le_bytes());
+                    bytes.extend_from_slice(&b.to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError>{
+        if !bytes.starts_with(MAGIC){
+            return Err(SnapshotError::NotASnapshot);
+        }
+        let mut reader = Reader { bytes, position: MAGIC.len() };
+        let version = reader.u32()?;
+        check_version(version)?;
+        let k = reader.u32()?;
+        check_k(k)?;
+        let vnodes_per_weight = reader.u32()?;
+        let seed = reader.u32()?;
+        let replication_factor = reader.u64()?;
+        let collision_policy = if version < SNAPSHOT_VERSION{
+            CollisionPolicy::Reject
+        } else {
+            policy_from_code(reader.u32()?)?
+        };
+        let mut nodes = vec![];
+        for _ in 0..reader.u64()?{
+            let hash_value = reader.u64()?;
+            let physical_id = reader.u64()?;
+            let standalone = version == SNAPSHOT_VERSION && reader.u8()? != 0;
+            let (zone, rack) = if version == UNLABELLED_VERSION{
+                (String::new(), String::new())
+            } else {
+                (reader.string()?, reader.string()?)
+            };
+            let resources = reader.pairs()?;
+            let fingers = reader.pairs()?;
+            nodes.push(NodeSnapshot { hash_value, physical_id, standalone, zone, rack, resources, fingers });
+        }
+        if reader.position != bytes.len(){
+            return Err(SnapshotError::TrailingBytes);
+        }
+        Ok(Self { version: SNAPSHOT_VERSION, k, vnodes_per_weight, seed, replication_factor, collision_policy, nodes })
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String{
+        serde_json::to_string_pretty(self).expect("a snapshot is always representable as JSON")
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<Self, SnapshotError>{
+        let mut snapshot: Self = serde_json::from_str(json).map_err(|e| SnapshotError::Json(e.to_string()))?;
+        check_version(snapshot.version)?;
+        check_k(snapshot.k)?;
+        snapshot.version = SNAPSHOT_VERSION;
+        Ok(snapshot)
+    }
+}
+
+fn check_version(version: u32) -> Result<(), SnapshotError>{
+    if ![UNLABELLED_VERSION, UNFLAGGED_VERSION, SNAPSHOT_VERSION].contains(&version){
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    Ok(())
+}
+
+fn policy_code(policy: CollisionPolicy) -> u32{
+    match policy{
+        CollisionPolicy::Reject => 0,
+        CollisionPolicy::Replace => 1,
+        CollisionPolicy::Rehash => 2,
+    }
+}
+
+fn policy_from_code(code: u32) -> Result<CollisionPolicy, SnapshotError>{
+    match code{
+        0 => Ok(CollisionPolicy::Reject),
+        1 => Ok(CollisionPolicy::Replace),
+        2 => Ok(CollisionPolicy::Rehash),
+        _ => Err(SnapshotError::UnknownPolicy(code)),
+    }
+}
+
+/// Rings exist for `k` from 1 to 64.
+pub fn check_k(k: u32) -> Result<(), SnapshotError>{
+    if !(1..=64).contains(&k){
+        return Err(SnapshotError::InvalidK(k));
+    }
+    Ok(())
+}
+
+struct Reader<'a>{
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_>{
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError>{
+        let end = self.position + N;
+        let chunk = self.bytes.get(self.position..end).ok_or(SnapshotError::Truncated)?;
+        self.position = end;
+        Ok(chunk.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError>{
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError>{
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError>{
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn string(&mut self) -> Result<String, SnapshotError>{
+        let len = self.u64()? as usize;
+        let end = self.position.checked_add(len).ok_or(SnapshotError::Truncated)?;
+        let chunk = self.bytes.get(self.position..end).ok_or(SnapshotError::Truncated)?;
+        self.position = end;
+        String::from_utf8(chunk.to_vec()).map_err(|_| SnapshotError::InvalidLabel)
+    }
+
+    fn pairs(&mut self) -> Result<Vec<(u64, u64)>, SnapshotError>{
+        let mut pairs = vec![];
+        for _ in 0..self.u64()?{
+            pairs.push((self.u64()?, self.u64()?));
+        }
+        Ok(pairs)
+    }
+}
@@ -1,19 +1,26 @@
-use std::{cell::RefCell, collections::HashMap, ops::Deref, rc::Rc};
+use std::{cell::RefCell, collections::{HashMap, HashSet}, rc::Rc};
 
+use crate::c02_key_hash::{space_max, KeyHasher};
+use crate::c02_ring_error::RingError;
+use crate::c02_ring_listener::RingListener;
 
-type NodeRef = Rc<RefCell<Node>>;
 
+pub type NodeRef = Rc<RefCell<Node>>;
 
-trait NodeRefExt{
+
+pub trait NodeRefExt{
     fn insert(self, hash_value: u64, value: u64);
 
     fn hash_value(&self) -> u64;
 
+    fn physical_id(&self) -> u64;
+
     fn resources(&self) -> HashMap<u64, u64>;
 
-    fn next(&self) -> NodeRef;
+    /// `None` until the node is linked into a ring, and again once it leaves.
+    fn next(&self) -> Option<NodeRef>;
 
-    fn previous(&self) -> NodeRef;
+    fn previous(&self) -> Option<NodeRef>;
 
     fn set_next(&self, next: NodeRef);
 
@@ -22,6 +29,8 @@ trait NodeRefExt{
     fn insert_resource(&self, hash_value: u64, value: u64);
 
     fn remove_resource(&self, hash_value: u64);
+
+    fn unlink(&self);
 }
 
 impl NodeRefExt for NodeRef{
@@ -33,24 +42,20 @@ impl NodeRefExt for NodeRef{
         self.as_ref().borrow().hash_value
     }
 
+    fn physical_id(&self) -> u64 {
+        self.as_ref().borrow().physical_id
+    }
+
     fn resources(&self) -> HashMap<u64, u64>{
         self.as_ref().borrow().resources.clone()
     }
 
-    fn next(&self) -> NodeRef{
-        if let Some(next) = self.as_ref().borrow().next.as_ref(){
-            next.clone()
-        }else{
-            panic!("Next node is None");
-        }
+    fn next(&self) -> Option<NodeRef>{
+        self.as_ref().borrow().next.clone()
     }
 
-    fn previous(&self) -> NodeRef{
-        if let Some(previous) = self.as_ref().borrow().previous.as_ref(){
-            previous.clone()
-        }else{
-            panic!("Previous node is None");
-        }
+    fn previous(&self) -> Option<NodeRef>{
+        self.as_ref().borrow().previous.clone()
     }
 
     fn set_next(&self, next: NodeRef){
@@ -68,92 +73,156 @@ impl NodeRefExt for NodeRef{
     fn remove_resource(&self, hash_value: u64){
         self.as_ref().borrow_mut().resources.remove(&hash_value);
     }
+
+    fn unlink(&self){
+        // drop the ring pointers so a departed node does not keep the ring alive
+        let mut node = self.as_ref().borrow_mut();
+        node.next = None;
+        node.previous = None;
+    }
 }
 
-struct Node{
+pub struct Node{
     hash_value: u64,
+    physical_id: u64, // physical node this token belongs to
     resources: HashMap<u64, u64>,
     next: Option<NodeRef>, // if none, refer to itself
     previous: Option<NodeRef>, // if none, refer to itself
+    standalone: bool, // made by `Node::new`, a physical node of its own
 }
 
 
 impl Node{
-    fn new(hash_value: u64) -> Self{
-        Self { hash_value, resources: HashMap::new(), next: None, previous: None }
+    /// A node that is a physical node of its own, with its hash as physical id.
+    pub fn new(hash_value: u64) -> Self{
+        Self { standalone: true, ..Self::new_virtual(hash_value, hash_value) }
     }
 
-    fn next(&self) -> NodeRef{
-        if let Some(next) = self.next.as_ref(){
-            next.clone()
-        }else{
-            panic!("Next node is None");
-        }
+    /// A virtual node: one of the tokens a physical node places on the ring.
+    pub fn new_virtual(hash_value: u64, physical_id: u64) -> Self{
+        Self { hash_value, physical_id, resources: HashMap::new(), next: None, previous: None, standalone: false }
+    }
+
+    pub fn next(&self) -> Option<NodeRef>{
+        self.next.clone()
     }
 
 }
 
-struct HashRing{
+// every node reachable from the head is linked in both directions
+fn next_of(node: &NodeRef) -> NodeRef{
+    node.next().expect("node on the ring has a successor")
+}
+
+fn previous_of(node: &NodeRef) -> NodeRef{
+    node.previous().expect("node on the ring has a predecessor")
+}
+
+pub struct HashRing{
     head: Option<NodeRef>,
     k: u32,
     min: u64,
     max: u64,
+    vnodes_per_weight: u32,
+    physical_nodes: HashMap<u64, Vec<u64>>, // physical id -> tokens on the ring
+    hasher: KeyHasher,
+    listeners: Vec<Box<dyn RingListener>>,
 }
 
 impl HashRing{
-    fn new(k: u32) -> Self{
-        Self { head: None, k, min: 0, max: 2u64.pow(k) - 1 }
+    pub fn new(k: u32) -> Self{
+        Self::with_vnodes(k, 1)
+    }
+
+    /// A ring where a physical node of weight `w` places `w * vnodes_per_weight` tokens.
+    pub fn with_vnodes(k: u32, vnodes_per_weight: u32) -> Self{
+        Self::with_seed(k, vnodes_per_weight, 0)
+    }
+
+    /// Like `with_vnodes`, with virtual node tokens hashed under murmur3 `seed`.
+    pub fn with_seed(k: u32, vnodes_per_weight: u32, seed: u32) -> Self{
+        Self {
+            head: None, k, min: 0, max: space_max(k), vnodes_per_weight, physical_nodes: HashMap::new(),
+            hasher: KeyHasher::new(k, seed), listeners: vec![],
+        }
     }
 
-    fn head(&self) -> NodeRef{
+    pub fn seed(&self) -> u32{
+        self.hasher.seed()
+    }
+
+    pub fn k(&self) -> u32{
+        self.k
+    }
+
+    pub fn vnodes_per_weight(&self) -> u32{
+        self.vnodes_per_weight
+    }
+
+    /// Registers `listener` for every event fired from now on.
+    pub fn add_listener(&mut self, listener: Box<dyn RingListener>){
+        self.listeners.push(listener);
+    }
+
+    fn notify(&mut self, event: impl Fn(&mut dyn RingListener)){
+        for listener in self.listeners.iter_mut(){
+            event(listener.as_mut());
+        }
+    }
+
+    pub fn head(&self) -> Result<NodeRef, RingError>{
         // in initial state, head is itself.
-        self.head.as_ref().unwrap().clone()
+        self.head.clone().ok_or(RingError::EmptyRing)
     }
 
-    fn head_mut(&mut self) -> &mut NodeRef{
-        self.head.as_mut().unwrap()
+    pub fn head_mut(&mut self) -> Result<&mut NodeRef, RingError>{
+        self.head.as_mut().ok_or(RingError::EmptyRing)
     }
 
-    fn is_in_legal_range(&self, hash_value: u64) -> bool {
+    // only for callers that already know the ring is not empty
+    fn head_node(&self) -> NodeRef{
+        self.head.clone().expect("ring is not empty")
+    }
+
+    pub fn is_in_legal_range(&self, hash_value: u64) -> bool {
         hash_value >= self.min && hash_value <= self.max
     }
 
-    fn distance(&self, a: u64, b: u64) -> u64{
-        if a == b {
-            0
-        } else if a < b {
-            b - a
-        } else {
-            2u64.pow(self.k) + b - a
-        }
+    /// Clockwise distance from `a` to `b`, wrapping modulo 2^k.
+    pub fn distance(&self, a: u64, b: u64) -> u64{
+        b.wrapping_sub(a) & self.max
     }
 
-    fn lookup_node_mut(&mut self, hash_value: u64) -> NodeRef{
-        if self.is_in_legal_range(hash_value){
-            let mut temp = self.head();
-            // let next = temp.next();
+    pub fn lookup_node(&self, hash_value: u64) -> Result<NodeRef, RingError>{
+        if !self.is_in_legal_range(hash_value){
+            return Err(RingError::OutOfRange(hash_value));
+        }
+        let mut temp = self.head()?;
+        if temp.hash_value() == hash_value{
+            return Ok(temp)
+        }
 
-            while self.distance(temp.hash_value(), hash_value) >
-                self.distance(temp.next().hash_value(), hash_value){
-                    // temp = temp.nesxt;``
-                    temp = temp.next();
-                    if temp.hash_value() == hash_value{
-                        return temp
-                    }
-            }
-            return temp.next();
+        while self.distance(temp.hash_value(), hash_value) >
+            self.distance(next_of(&temp).hash_value(), hash_value){
+                temp = next_of(&temp);
+                if temp.hash_value() == hash_value{
+                    return Ok(temp)
+                }
         }
-        panic!("Hash value out of range");
+        Ok(next_of(&temp))
     }
 
-    pub fn add_resource(&mut self, hash_value: u64){
-        if self.is_in_legal_range(hash_value){
-            println!("Adding a resource {} ...", hash_value);
-            let target_node = self.lookup_node_mut(hash_value);
-            let target_node_hash = target_node.hash_value();
-            target_node.insert(hash_value, hash_value);
-            println!("Added resource with hash value {} to node {}", hash_value, target_node_hash);
-        }
+    /// Resolves `hash_value` to the physical node owning the token it falls on.
+    pub fn lookup_physical_node(&self, hash_value: u64) -> Result<u64, RingError>{
+        self.lookup_node(hash_value).map(|node| node.physical_id())
+    }
+
+    pub fn add_resource(&mut self, hash_value: u64) -> Result<(), RingError>{
+        let target_node = self.lookup_node(hash_value)?;
+        let target_node_hash = target_node.hash_value();
+        target_node.insert(hash_value, hash_value);
+        self.notify(|listener| listener.on_resource_added(hash_value, target_node_hash));
+        Ok(())
     }
 
 
@@ -168,32 +237,139 @@ impl HashRing{
 
         for i in delete_list.iter(){
             orig.remove_resource(*i);
+            self.notify(|listener| listener.on_resource_moved(*i, orig.hash_value(), dest.hash_value()));
         }
     }
 
-    fn add_node(&mut self, new_node: NodeRef){
-        if self.is_in_legal_range(new_node.hash_value()){
-            if self.head.is_none(){
-                new_node.set_next(new_node.clone());
-                new_node.set_previous(new_node.clone());
+    pub fn add_node(&mut self, new_node: NodeRef) -> Result<(), RingError>{
+        let hash_value = new_node.hash_value();
+        if !self.is_in_legal_range(hash_value){
+            return Err(RingError::OutOfRange(hash_value));
+        }
+        if new_node.as_ref().borrow().standalone && self.physical_nodes.contains_key(&new_node.physical_id()){
+            return Err(RingError::DuplicateNode(new_node.physical_id()));
+        }
+        if self.head.is_none(){
+            new_node.set_next(new_node.clone());
+            new_node.set_previous(new_node.clone());
+            self.head = Some(new_node.clone());
+            self.notify(|listener| listener.on_node_joined(hash_value));
+        }else{
+            let temp = self.lookup_node(hash_value)?;
+            if temp.hash_value() == hash_value{
+                return Err(RingError::DuplicateNode(hash_value));
+            }
+            new_node.set_next(temp.clone());
+            new_node.set_previous(previous_of(&temp));
+            temp.set_previous(new_node.clone());
+            previous_of(&new_node).set_next(new_node.clone());
+            self.notify(|listener| listener.on_node_joined(hash_value));
+
+            self.move_resources(new_node.clone(), temp, false);
+            if hash_value < self.head_node().hash_value(){
                 self.head = Some(new_node.clone());
-                println!("Added head node with hash value {}", new_node.hash_value());
-            }else{
-                let temp = self.lookup_node_mut(new_node.hash_value());
-                new_node.set_next(temp.clone());
-                new_node.set_previous(temp.previous());
-                new_node.next().set_previous(new_node.clone());
-                new_node.previous().set_next(new_node.clone());
-                println!("Added node with hash value {}", new_node.hash_value());
-                println!("Its prev is {}", new_node.previous().hash_value());
-                println!("Its next is {}", new_node.next().hash_value());
-
-                self.move_resources(new_node.clone(), new_node.next(),false);
-                if new_node.hash_value() < self.head().hash_value(){
-                    self.head = Some(new_node);
-                }
             }
         }
+        self.physical_nodes.entry(new_node.physical_id()).or_default().push(hash_value);
+        Ok(())
+    }
+
+    /// Takes the node owning `hash_value` out of the ring and hands all of its
+    /// resources over to its successor. Returns the departed node.
+    pub fn remove_node(&mut self, hash_value: u64) -> Result<NodeRef, RingError>{
+        if !self.is_in_legal_range(hash_value){
+            return Err(RingError::OutOfRange(hash_value));
+        }
+        let node = match self.lookup_node(hash_value){
+            Ok(node) if node.hash_value() == hash_value => node,
+            _ => return Err(RingError::UnknownNode(hash_value)),
+        };
+
+        if let Some(tokens) = self.physical_nodes.get_mut(&node.physical_id()){
+            tokens.retain(|token| *token != hash_value);
+            if tokens.is_empty(){
+                self.physical_nodes.remove(&node.physical_id());
+            }
+        }
+
+        let successor = next_of(&node);
+        if Rc::ptr_eq(&successor, &node){
+            // the last node leaves, nobody is left to take over its resources
+            self.head = None;
+            node.unlink();
+            self.notify(|listener| listener.on_node_left(hash_value));
+            return Ok(node);
+        }
+
+        let predecessor = previous_of(&node);
+        predecessor.set_next(successor.clone());
+        successor.set_previous(predecessor.clone());
+        self.notify(|listener| listener.on_node_left(hash_value));
+
+        self.move_resources(successor.clone(), node.clone(), true);
+        if Rc::ptr_eq(&node, &self.head_node()){
+            // head is the minimum, so its successor is the next smallest token
+            self.head = Some(successor);
+        }
+        node.unlink();
+        Ok(node)
+    }
+
+    fn vnode_token(&self, physical_id: u64, index: u32) -> u64{
+        self.hasher.hash(&(physical_id, index))
+    }
+
+    /// Places `weight * vnodes_per_weight` tokens for `physical_id` on the ring
+    /// and returns them. If any token would land on an occupied position,
+    /// nothing is placed.
+    pub fn add_physical_node(&mut self, physical_id: u64, weight: u32) -> Result<Vec<u64>, RingError>{
+        let count = weight.checked_mul(self.vnodes_per_weight).ok_or(RingError::TooManyTokens(weight))?;
+        if self.physical_nodes.contains_key(&physical_id){
+            return Err(RingError::DuplicateNode(physical_id));
+        }
+        let mut taken = self.physical_nodes.values().flatten().copied().collect::<HashSet<u64>>();
+        let tokens = (0..count).map(|index| self.vnode_token(physical_id, index)).collect::<Vec<u64>>();
+        for token in tokens.iter(){
+            if !taken.insert(*token){
+                return Err(RingError::DuplicateNode(*token));
+            }
+        }
+        for token in tokens.iter(){
+            self.add_node(RefCell::new(Node::new_virtual(*token, physical_id)).into()).expect("token was checked to be free");
+        }
+        Ok(tokens)
+    }
+
+    /// Removes every token of `physical_id`, handing each token's resources to
+    /// its successor. Nothing is removed unless every token is on the ring.
+    pub fn remove_physical_node(&mut self, physical_id: u64) -> Result<(), RingError>{
+        let tokens = self.physical_nodes.get(&physical_id).cloned().ok_or(RingError::UnknownNode(physical_id))?;
+        for token in tokens.iter(){
+            match self.lookup_node(*token){
+                Ok(node) if node.hash_value() == *token && node.physical_id() == physical_id => {},
+                _ => return Err(RingError::UnknownNode(*token)),
+            }
+        }
+        for token in tokens{
+            self.remove_node(token).expect("token was checked to be on the ring");
+        }
+        Ok(())
+    }
+
+    pub fn physical_nodes(&self) -> Vec<u64>{
+        let mut ids = self.physical_nodes.keys().copied().collect::<Vec<u64>>();
+        ids.sort();
+        ids
+    }
+
+    pub fn tokens_of(&self, physical_id: u64) -> Vec<u64>{
+        self.physical_nodes.get(&physical_id).cloned().unwrap_or_default()
+    }
+
+    /// Number of tokens below `hash_value`, the nodes `lookup_node` passes on
+    /// its walk from the head.
+    pub fn tokens_below(&self, hash_value: u64) -> usize{
+        self.physical_nodes.values().flatten().filter(|token| **token < hash_value).count()
     }
 
     pub fn print_hash_ring(&self){
@@ -203,15 +379,15 @@ impl HashRing{
             return;
         }
 
-        let mut temp = self.head();
+        let mut temp = self.head_node();
         loop{
             {
                 let node = temp.clone();
-                println!("Node hash value: {}", node.hash_value());
+                println!("Node hash value: {} (physical node {})", node.hash_value(), node.physical_id());
                 println!("Resources: {:?}", node.resources().keys().collect::<Vec<&u64>>());
             }
-            temp = temp.next();
-            if temp.hash_value() == self.head().hash_value(){
+            temp = next_of(&temp);
+            if temp.hash_value() == self.head_node().hash_value(){
                 break;
             }
         }
@@ -222,6 +398,13 @@ impl HashRing{
 #[cfg(test)]
 mod tests{
     use super::*;
+    use crate::c02_ring_listener::LoggingListener;
+
+    #[test]
+    #[should_panic]
+    fn test_k_out_of_range(){
+        HashRing::new(65);
+    }
 
     #[test]
     fn test_distance() {
@@ -235,23 +418,89 @@ mod tests{
     fn test_scenario(){
         // stdout used
         let mut hr = HashRing::new(5);
-        hr.add_node(RefCell::new(Node::new(12)).into());
-        hr.add_node(RefCell::new(Node::new(18)).into());
-        hr.add_resource(24);
-        hr.add_resource(21);
-        hr.add_resource(16);
-        hr.add_resource(23);
-        hr.add_resource(2);
-        hr.add_resource(29);
-        hr.add_resource(28);
-        hr.add_resource(7);
-        hr.add_resource(10);
+        hr.add_listener(Box::new(LoggingListener));
+        hr.add_node(RefCell::new(Node::new(12)).into()).unwrap();
+        hr.add_node(RefCell::new(Node::new(18)).into()).unwrap();
+        hr.add_resource(24).unwrap();
+        hr.add_resource(21).unwrap();
+        hr.add_resource(16).unwrap();
+        hr.add_resource(23).unwrap();
+        hr.add_resource(2).unwrap();
+        hr.add_resource(29).unwrap();
+        hr.add_resource(28).unwrap();
+        hr.add_resource(7).unwrap();
+        hr.add_resource(10).unwrap();
         hr.print_hash_ring();
 
-        hr.add_node(RefCell::new(Node::new(5)).into());
-        hr.add_node(RefCell::new(Node::new(27)).into());
-        hr.add_node(RefCell::new(Node::new(30)).into());
+        hr.add_node(RefCell::new(Node::new(5)).into()).unwrap();
+        hr.add_node(RefCell::new(Node::new(27)).into()).unwrap();
+        hr.add_node(RefCell::new(Node::new(30)).into()).unwrap();
         hr.print_hash_ring();
     }
 
+    #[test]
+    fn test_physical_nodes(){
+        let mut hr = HashRing::with_vnodes(12, 4);
+        let light = hr.add_physical_node(1, 1).unwrap();
+        let heavy = hr.add_physical_node(2, 2).unwrap();
+        assert_eq!(4, light.len());
+        assert_eq!(8, heavy.len());
+        assert_eq!(Err(RingError::TooManyTokens(u32::MAX)), hr.add_physical_node(3, u32::MAX));
+        assert_eq!(Err(RingError::DuplicateNode(1)), hr.add_physical_node(1, 1));
+        for token in heavy.iter(){
+            assert_eq!(Ok(2), hr.lookup_physical_node(*token));
+        }
+
+        for hash_value in (0..hr.max).step_by(31){
+            hr.add_resource(hash_value).unwrap();
+        }
+        assert_eq!(Ok(()), hr.remove_physical_node(2));
+        assert_eq!(vec![1], hr.physical_nodes());
+        let total: usize = hr.tokens_of(1).iter()
+            .map(|token| hr.lookup_node(*token).unwrap().resources().len())
+            .sum();
+        assert_eq!((0..hr.max).step_by(31).count(), total);
+
+        // the seed decides where the tokens go
+        let mut seeded = HashRing::with_seed(12, 4, 9);
+        assert_ne!(light, seeded.add_physical_node(1, 1).unwrap());
+        assert_eq!(9, seeded.seed());
+
+        // 16 tokens cannot all fit in a 4-bit space; none of them is placed
+        let mut full = HashRing::with_vnodes(4, 16);
+        assert!(matches!(full.add_physical_node(1, 1), Err(RingError::DuplicateNode(_))));
+        assert!(full.physical_nodes().is_empty());
+        assert!(full.head().is_err());
+    }
+
+    #[test]
+    fn test_remove_node(){
+        let mut hr = HashRing::new(5);
+        hr.add_node(RefCell::new(Node::new(5)).into()).unwrap();
+        hr.add_node(RefCell::new(Node::new(12)).into()).unwrap();
+        hr.add_node(RefCell::new(Node::new(18)).into()).unwrap();
+        hr.add_resource(2).unwrap();
+        hr.add_resource(7).unwrap();
+        hr.add_resource(10).unwrap();
+        hr.add_resource(16).unwrap();
+
+        assert!(matches!(hr.remove_node(13), Err(RingError::UnknownNode(13))));
+
+        let departed = hr.remove_node(12).unwrap();
+        assert!(departed.resources().is_empty());
+        let successor = hr.lookup_node(10).unwrap();
+        assert_eq!(18, successor.hash_value());
+        let mut keys = successor.resources().keys().copied().collect::<Vec<u64>>();
+        keys.sort();
+        assert_eq!(vec![7, 10, 16], keys);
+
+        // head leaves, its successor becomes the new head
+        hr.remove_node(5).unwrap();
+        assert_eq!(18, hr.head().unwrap().hash_value());
+        assert_eq!(4, hr.head().unwrap().resources().len());
+
+        hr.remove_node(18).unwrap();
+        assert!(hr.head.is_none());
+    }
+
 }
\ No newline at end of file
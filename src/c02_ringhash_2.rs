@@ -1,36 +1,59 @@
-use std::{cell::RefCell, collections::HashMap, ops::Deref, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, f64::consts::PI, fmt::Write, hash::Hash, rc::Rc};
 
-type NodeRef = Rc<RefCell<Node>>;
+use crate::c02_key_hash::{space_max, KeyHasher};
+use crate::c02_migration::{MembershipChange, ResourceMove};
+use crate::c02_ring_error::RingError;
+use crate::c02_ring_listener::RingListener;
+use crate::c02_ring_snapshot::{check_k, NodeSnapshot, RingSnapshot, SnapshotError, SNAPSHOT_VERSION};
 
+pub type NodeRef<V = u64> = Rc<RefCell<Node<V>>>;
 
-pub trait NodeRefExt{
-    fn insert(self, hash_value: u64, value: u64);
+
+pub trait NodeRefExt<V>{
+    fn insert(self, hash_value: u64, value: V);
 
     fn hash_value(&self) -> u64;
 
-    fn resources(&self) -> HashMap<u64, u64>;
+    fn physical_id(&self) -> u64;
+
+    fn location(&self) -> Location;
+
+    fn resources(&self) -> HashMap<u64, V>;
+
+    /// `None` until the node is linked into a ring, and again once it leaves.
+    fn next(&self) -> Option<NodeRef<V>>;
+
+    fn previous(&self) -> Option<NodeRef<V>>;
+
+    fn set_next(&self, next: NodeRef<V>);
+
+    fn set_previous(&   self, previous: NodeRef<V>);
+
+    fn insert_resource(&self, hash_value: u64, value: V);
 
-    fn next(&self) -> NodeRef;
+    fn resource(&self, hash_value: u64) -> Option<V>;
 
-    fn previous(&self) -> NodeRef;
+    fn remove_resource(&self, hash_value: u64) -> Option<V>;
 
-    fn set_next(&self, next: NodeRef);
+    fn replicas(&self) -> HashMap<u64, V>;
 
-    fn set_previous(&   self, previous: NodeRef);
+    fn insert_replica(&self, hash_value: u64, value: V);
 
-    fn insert_resource(&self, hash_value: u64, value: u64);
+    fn remove_replica(&self, hash_value: u64);
 
-    fn remove_resource(&self, hash_value: u64);
+    fn clear_replicas(&self);
 
-    fn set_finger(&self, index: u64, node: NodeRef);
+    fn set_finger(&self, index: u64, node: NodeRef<V>);
 
-    fn finger_table(&self) -> HashMap<u64, NodeRef>;
+    fn finger_table(&self) -> HashMap<u64, NodeRef<V>>;
 
     fn inspect_finger_table(&self) -> Vec<(u64, u64)>;
+
+    fn unlink(&self);
 }
 
-impl NodeRefExt for NodeRef{
-    fn insert(self, hash_value: u64, value: u64){
+impl<V: Clone> NodeRefExt<V> for NodeRef<V>{
+    fn insert(self, hash_value: u64, value: V){
         self.as_ref().borrow_mut().resources.insert(hash_value, value);
     }
 
@@ -38,325 +61,2331 @@ impl NodeRefExt for NodeRef{
         self.as_ref().borrow().hash_value
     }
 
-    fn resources(&self) -> HashMap<u64, u64>{
+    fn physical_id(&self) -> u64 {
+        self.as_ref().borrow().physical_id
+    }
+
+    fn location(&self) -> Location{
+        self.as_ref().borrow().location.clone()
+    }
+
+    fn resources(&self) -> HashMap<u64, V>{
         self.as_ref().borrow().resources.clone()
     }
 
-    fn next(&self) -> NodeRef{
-        if let Some(next) = self.as_ref().borrow().next.as_ref(){
-            next.clone()
-        }else{
-            panic!("Next node is None");
-        }
+    fn next(&self) -> Option<NodeRef<V>>{
+        self.as_ref().borrow().next.clone()
     }
 
-    fn previous(&self) -> NodeRef{
-        if let Some(previous) = self.as_ref().borrow().previous.as_ref(){
-            previous.clone()
-        }else{
-            panic!("Previous node is None");
-        }
+    fn previous(&self) -> Option<NodeRef<V>>{
+        self.as_ref().borrow().previous.clone()
     }
 
-    fn set_next(&self, next: NodeRef){
+    fn set_next(&self, next: NodeRef<V>){
         self.as_ref().borrow_mut().next = Some(next);
     }
 
-    fn set_previous(&self, previous: NodeRef){
+    fn set_previous(&self, previous: NodeRef<V>){
         self.as_ref().borrow_mut().previous = Some(previous);
     }
 
-    fn insert_resource(&self, hash_value: u64, value: u64){
+    fn insert_resource(&self, hash_value: u64, value: V){
         self.as_ref().borrow_mut().resources.insert(hash_value, value);
     }
 
-    fn remove_resource(&self, hash_value: u64){
-        self.as_ref().borrow_mut().resources.remove(&hash_value);
+    fn resource(&self, hash_value: u64) -> Option<V>{
+        self.as_ref().borrow().resources.get(&hash_value).cloned()
+    }
+
+    fn remove_resource(&self, hash_value: u64) -> Option<V>{
+        self.as_ref().borrow_mut().resources.remove(&hash_value)
+    }
+
+    fn replicas(&self) -> HashMap<u64, V>{
+        self.as_ref().borrow().replicas.clone()
+    }
+
+    fn insert_replica(&self, hash_value: u64, value: V){
+        self.as_ref().borrow_mut().replicas.insert(hash_value, value);
+    }
+
+    fn remove_replica(&self, hash_value: u64){
+        self.as_ref().borrow_mut().replicas.remove(&hash_value);
+    }
+
+    fn clear_replicas(&self){
+        self.as_ref().borrow_mut().replicas.clear();
     }
 
-    fn set_finger(&self, index: u64, node: NodeRef) {
+    fn set_finger(&self, index: u64, node: NodeRef<V>) {
         self.as_ref().borrow_mut().finger_table.insert(index, node);
     }
 
-    fn finger_table(&self) -> HashMap<u64, NodeRef> {
+    fn finger_table(&self) -> HashMap<u64, NodeRef<V>> {
         self.as_ref().borrow().finger_table.clone()
     }
 
     fn inspect_finger_table(&self) -> Vec<(u64, u64)> {
         self.as_ref().borrow().finger_table.iter().map(|(k, v)| (*k, v.hash_value())).collect()
     }
+
+    fn unlink(&self){
+        // drop the ring pointers so a departed node does not keep the ring alive
+        let mut node = self.as_ref().borrow_mut();
+        node.next = None;
+        node.previous = None;
+        node.finger_table.clear();
+        node.replicas.clear();
+    }
+}
+
+/// Failure domain of a node. Nodes without labels all share the empty
+/// domain.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Location{
+    pub zone: String,
+    pub rack: String,
+}
+
+impl Location{
+    pub fn new(zone: &str, rack: &str) -> Self{
+        Self { zone: zone.to_string(), rack: rack.to_string() }
+    }
 }
 
-pub struct Node{
+pub struct Node<V = u64>{
     hash_value: u64,
-    resources: HashMap<u64, u64>,
-    next: Option<NodeRef>, // if none, refer to itself
-    finger_table: HashMap<u64, NodeRef>,
-    previous: Option<NodeRef>, // if none, refer to itself
+    physical_id: u64, // physical node this token belongs to
+    location: Location,
+    resources: HashMap<u64, V>,
+    replicas: HashMap<u64, V>, // copies of resources owned by a predecessor
+    next: Option<NodeRef<V>>, // if none, refer to itself
+    finger_table: HashMap<u64, NodeRef<V>>,
+    previous: Option<NodeRef<V>>, // if none, refer to itself
+    standalone: bool, // made by `Node::new`, a physical node of its own
 }
 
 
-impl Node{
-    fn new(hash_value: u64) -> Self{
-        Self { hash_value, resources: HashMap::new(), next: None, previous: None, finger_table: HashMap::new() }
+impl<V> Node<V>{
+    /// A node that is a physical node of its own, with its hash as physical id.
+    pub fn new(hash_value: u64) -> Self{
+        Self { standalone: true, ..Self::new_virtual(hash_value, hash_value) }
     }
 
-    fn next(&self) -> NodeRef{
-        if let Some(next) = self.next.as_ref(){
-            next.clone()
-        }else{
-            panic!("Next node is None");
+    /// A virtual node: one of the tokens a physical node places on the ring.
+    pub fn new_virtual(hash_value: u64, physical_id: u64) -> Self{
+        Self {
+            hash_value, physical_id, location: Location::default(), resources: HashMap::new(),
+            replicas: HashMap::new(), next: None, previous: None, finger_table: HashMap::new(),
+            standalone: false,
         }
     }
 
+    pub fn with_location(mut self, location: Location) -> Self{
+        self.location = location;
+        self
+    }
+
+    pub fn next(&self) -> Option<NodeRef<V>>{
+        self.next.clone()
+    }
+
+}
+
+// every node reachable from the head is linked in both directions
+fn next_of<V: Clone>(node: &NodeRef<V>) -> NodeRef<V>{
+    node.next().expect("node on the ring has a successor")
+}
+
+fn previous_of<V: Clone>(node: &NodeRef<V>) -> NodeRef<V>{
+    node.previous().expect("node on the ring has a predecessor")
+}
+
+// a node with the token, physical id and location of `node`, and nothing else
+fn detached_copy<V: Clone>(node: &NodeRef<V>) -> NodeRef<V>{
+    let node = node.as_ref().borrow();
+    let copy = Node::new_virtual(node.hash_value, node.physical_id).with_location(node.location.clone());
+    RefCell::new(Node { standalone: node.standalone, ..copy }).into()
+}
+
+/// The resources a single node joining or leaving would move, computed
+/// without touching the ring. Execute it with `HashRing::apply_plan`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationPlan{
+    pub change: MembershipChange,
+    /// Physical node and location of the token joining or leaving.
+    pub physical_id: u64,
+    pub location: Location,
+    pub moves: Vec<ResourceMove>,
+    /// Replica copies a replicated ring creates or drops, sorted by key.
+    pub replicas_added: Vec<ReplicaCopy>,
+    pub replicas_dropped: Vec<ReplicaCopy>,
+    standalone: bool,
+}
+
+impl MigrationPlan{
+    // the node a join plan adds, at `hash_value`
+    fn joining_node<V>(&self, hash_value: u64) -> NodeRef<V>{
+        let node = Node::new_virtual(hash_value, self.physical_id).with_location(self.location.clone());
+        RefCell::new(Node { standalone: self.standalone, ..node }).into()
+    }
+}
+
+/// A replica copy of `key` on the token `node`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplicaCopy{
+    pub key: u64,
+    pub node: u64,
+}
+
+/// A key whose copies share a failure domain, as reported by
+/// `HashRing::colocation_report`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Colocation{
+    pub key: u64,
+    /// Physical nodes holding a copy, owner first.
+    pub holders: Vec<u64>,
+    /// Zones holding more than one copy.
+    pub zones: Vec<String>,
+    /// Whether two copies are on the same rack as well.
+    pub same_rack: bool,
+}
+
+/// Load of one physical node, as reported by `HashRing::stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeStats{
+    pub physical_id: u64,
+    pub tokens: usize,
+    pub resources: usize,
+    /// Share of the 2^k hash space owned through all of its tokens.
+    pub ownership: f64,
 }
 
-struct HashRing{
-    head: Option<NodeRef>,
+/// Load distribution over the physical nodes of a ring.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RingStats{
+    pub nodes: Vec<NodeStats>,
+    pub total_resources: usize,
+    pub mean: f64,
+    pub stddev: f64,
+    pub max_over_mean: f64,
+    /// 0 when every node holds the same number of resources, towards 1 when
+    /// a single node holds them all.
+    pub gini: f64,
+}
+
+/// A broken ring invariant, as reported by `HashRing::validate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation{
+    /// `node.next().previous()` is not `node`.
+    BrokenLink{ node: u64, next: u64 },
+    /// Walking `next` from the head did not come back to it after visiting
+    /// every registered token.
+    TokenCount{ registered: usize, linked: usize },
+    /// A token is not larger than its predecessor, away from the wrap.
+    OutOfOrder{ node: u64, next: u64 },
+    HeadNotMinimum{ head: u64, minimum: u64 },
+    /// A resource is not on the successor of its key.
+    MisplacedResource{ key: u64, node: u64, owner: u64 },
+    /// The finger for `range` is not the successor of `node + range`.
+    WrongFinger{ node: u64, range: u64, finger: u64, expected: u64 },
+    /// A linked token is not among the tokens registered for its physical id.
+    UnregisteredToken{ node: u64, physical_id: u64 },
+}
+
+/// What `add_node` does when the new node's hash is already taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CollisionPolicy{
+    /// Refuse the node with `RingError::DuplicateNode`.
+    #[default]
+    Reject,
+    /// The new node takes the position over, along with the old node's
+    /// resources. The old node leaves the ring.
+    Replace,
+    /// Hash the position again with salts 1, 2, ... until a free one turns up,
+    /// giving up with `RingError::DuplicateNode` after `MAX_REHASH_SALTS`.
+    /// The new node's hash value is updated to where it ended up; a node made
+    /// with `Node::new` also takes that as its physical id.
+    Rehash,
+}
+
+/// Salts `CollisionPolicy::Rehash` tries before refusing a node.
+pub const MAX_REHASH_SALTS: u64 = 64;
+
+pub struct HashRing<V = u64>{
+    head: Option<NodeRef<V>>,
     k: u32,
     min: u64,
     max: u64,
+    vnodes_per_weight: u32,
+    physical_nodes: HashMap<u64, Vec<u64>>, // physical id -> tokens on the ring
+    hasher: KeyHasher,
+    replication_factor: usize,
+    collision_policy: CollisionPolicy,
+    listeners: Vec<Box<dyn RingListener>>,
 }
 
-impl HashRing{
-    fn new(k: u32) -> Self{
-        Self { head: None, k, min: 0, max: 2u64.pow(k) - 1 }
+impl<V: Clone> HashRing<V>{
+    /// A ring over the hash space [0, 2^k), for any `k` from 1 to 64. Panics
+    /// for any other `k`.
+    pub fn new(k: u32) -> Self{
+        Self::with_vnodes(k, 1)
+    }
+
+    /// A ring where a physical node of weight `w` places `w * vnodes_per_weight` tokens.
+    pub fn with_vnodes(k: u32, vnodes_per_weight: u32) -> Self{
+        Self::with_seed(k, vnodes_per_weight, 0)
+    }
+
+    /// Like `with_vnodes`, with keys and node names hashed under murmur3 `seed`.
+    pub fn with_seed(k: u32, vnodes_per_weight: u32, seed: u32) -> Self{
+        Self {
+            head: None, k, min: 0, max: space_max(k),
+            vnodes_per_weight, physical_nodes: HashMap::new(),
+            hasher: KeyHasher::new(k, seed),
+            replication_factor: 1,
+            collision_policy: CollisionPolicy::Reject,
+            listeners: vec![],
+        }
+    }
+
+    /// Position of `key` in the `k`-bit hash space.
+    pub fn hash_key<K: Hash + ?Sized>(&self, key: &K) -> u64{
+        self.hasher.hash(key)
     }
 
     fn finger_ranges(&self) -> Vec<u64>{
         let mut ranges = vec![];
         for i in 0..self.k{
-            ranges.push(2u64.pow(i));
+            ranges.push(1u64 << i);
         }
         ranges
     }
 
-    fn head(&self) -> NodeRef{
+    pub fn k(&self) -> u32{
+        self.k
+    }
+
+    pub fn vnodes_per_weight(&self) -> u32{
+        self.vnodes_per_weight
+    }
+
+    pub fn seed(&self) -> u32{
+        self.hasher.seed()
+    }
+
+    /// Every node on the ring, in token order starting from `head`.
+    pub fn nodes(&self) -> Vec<NodeRef<V>>{
+        let mut nodes = vec![];
+        if self.head.is_none(){
+            return nodes;
+        }
+        let head = self.head_node();
+        let mut temp = head.clone();
+        loop{
+            nodes.push(temp.clone());
+            temp = next_of(&temp);
+            if Rc::ptr_eq(&temp, &head){
+                break;
+            }
+        }
+        nodes
+    }
+
+    /// Registers `listener` for every event fired from now on.
+    pub fn add_listener(&mut self, listener: Box<dyn RingListener>){
+        self.listeners.push(listener);
+    }
+
+    fn notify(&mut self, event: impl Fn(&mut dyn RingListener)){
+        for listener in self.listeners.iter_mut(){
+            event(listener.as_mut());
+        }
+    }
+
+    pub fn head(&self) -> Result<NodeRef<V>, RingError>{
         // in initial state, head is itself.
-        self.head.as_ref().unwrap().clone()
+        self.head.clone().ok_or(RingError::EmptyRing)
+    }
+
+    pub fn head_mut(&mut self) -> Result<&mut NodeRef<V>, RingError>{
+        self.head.as_mut().ok_or(RingError::EmptyRing)
+    }
+
+    // only for callers that already know the ring is not empty
+    fn head_node(&self) -> NodeRef<V>{
+        self.head.clone().expect("ring is not empty")
     }
 
-    fn head_mut(&mut self) -> &mut NodeRef{
-        self.head.as_mut().unwrap()
+    fn check_lookup(&self, hash_value: u64) -> Result<(), RingError>{
+        if !self.is_in_legal_range(hash_value){
+            return Err(RingError::OutOfRange(hash_value));
+        }
+        if self.head.is_none(){
+            return Err(RingError::EmptyRing);
+        }
+        Ok(())
     }
 
-    fn is_in_legal_range(&self, hash_value: u64) -> bool {
+    pub fn is_in_legal_range(&self, hash_value: u64) -> bool {
         hash_value >= self.min && hash_value <= self.max
     }
 
-    fn distance(&self, a: u64, b: u64) -> u64{
-        if a == b {
-            0
-        } else if a < b {
-            b - a
-        } else {
-            2u64.pow(self.k) + b - a
+    /// Clockwise distance from `a` to `b`, wrapping modulo 2^k.
+    pub fn distance(&self, a: u64, b: u64) -> u64{
+        b.wrapping_sub(a) & self.max
+    }
+
+    pub fn lookup_node(&self, hash_value: u64) -> Result<NodeRef<V>, RingError>{
+        self.check_lookup(hash_value)?;
+        let mut temp = self.head_node();
+        if temp.hash_value() == hash_value{
+            return Ok(temp)
+        }
+
+        while self.distance(temp.hash_value(), hash_value) >
+            self.distance(next_of(&temp).hash_value(), hash_value){
+                temp = next_of(&temp);
+                if temp.hash_value() == hash_value{
+                    return Ok(temp)
+                }
         }
+        Ok(next_of(&temp))
+    }
+
+    pub fn chord_lookup(&self, hash_value: u64) -> Result<NodeRef<V>, RingError>{
+        self.check_lookup(hash_value)?;
+        Ok(self.find_successor(hash_value))
+    }
+
+    /// Nodes `chord_lookup` steps through to resolve `hash_value`.
+    pub fn lookup_hops(&self, hash_value: u64) -> Result<usize, RingError>{
+        self.check_lookup(hash_value)?;
+        Ok(self.walk_to_successor(hash_value).1)
     }
 
-    fn lookup_node(&mut self, hash_value: u64) -> NodeRef{
-        if self.is_in_legal_range(hash_value){
-            let mut temp = self.head();
-            // let next = temp.next();
+    // the finger walk behind `chord_lookup`, for an in-range hash on a non-empty ring
+    fn find_successor(&self, hash_value: u64) -> NodeRef<V>{
+        self.walk_to_successor(hash_value).0
+    }
 
-            while self.distance(temp.hash_value(), hash_value) >
-                self.distance(temp.next().hash_value(), hash_value){
-                    // temp = temp.nesxt;``
-                    temp = temp.next();
+    // the successor of `hash_value` and the number of hops taken to reach it
+    fn walk_to_successor(&self, hash_value: u64) -> (NodeRef<V>, usize){
+        let mut temp = self.head_node();
+        let mut hops = 0;
+        if temp.hash_value() == hash_value{
+            return (temp, hops)
+        }
+        loop{
+            // the finger closest to `hash_value` without passing it
+            let remaining = self.distance(temp.hash_value(), hash_value);
+            let closest = temp.as_ref().borrow().finger_table.values()
+                .filter(|node| self.distance(node.hash_value(), hash_value) < remaining)
+                .min_by_key(|node| self.distance(node.hash_value(), hash_value))
+                .cloned();
+
+            match closest{
+                Some(node) => {
+                    temp = node;
+                    hops += 1;
                     if temp.hash_value() == hash_value{
-                        return temp
+                        return (temp, hops)
+                    }
+                }
+                None => {
+                    while self.distance(temp.hash_value(), hash_value) >
+                        self.distance(next_of(&temp).hash_value(), hash_value){
+                        temp = next_of(&temp);
+                        hops += 1;
+                        if temp.hash_value() == hash_value{
+                            return (temp, hops)
+                        }
                     }
+                    return (next_of(&temp), hops + 1);
+                }
             }
-            return temp.next();
         }
-        panic!("Hash value out of range");
     }
 
-    pub fn chord_lookup(&mut self, hash_value: u64) -> NodeRef{
-        if self.is_in_legal_range(hash_value){
-            let mut temp = self.head();
-            loop{
-                let finger_table = temp.finger_table();
-                let mut found = false;
+    /// Resolves `hash_value` to the physical node owning the token it falls on.
+    pub fn lookup_physical_node(&self, hash_value: u64) -> Result<u64, RingError>{
+        self.chord_lookup(hash_value).map(|node| node.physical_id())
+    }
 
-                for (_i, node) in finger_table.iter(){
-                    if self.distance(temp.hash_value(), hash_value) >
-                        self.distance(node.hash_value(), hash_value){
-                            temp = node.clone();
-                            found = true;
-                            break;
-                        }
+    /// Physical node responsible for `key`.
+    pub fn node_for<K: Hash + ?Sized>(&self, key: &K) -> Result<u64, RingError>{
+        let hash_value = self.hash_key(key);
+        self.lookup_physical_node(hash_value)
+    }
+
+    /// Resources whose hash lies on the clockwise arc `[a, b]`, ordered from
+    /// `a`. Arcs may wrap past zero; only the nodes owning part of the arc are
+    /// visited.
+    pub fn resources_in_range(&self, a: u64, b: u64) -> Result<Vec<(u64, V)>, RingError>{
+        self.check_lookup(a)?;
+        self.check_lookup(b)?;
+        let span = self.distance(a, b);
+        let start = self.find_successor(a);
+        let mut temp = start.clone();
+        let mut found = Vec::new();
+        loop{
+            found.extend(temp.resources().into_iter()
+                .filter(|(hash_value, _)| self.distance(a, *hash_value) <= span));
+            // this node's token is at or past `b`, so it owns the end of the arc
+            if self.distance(a, temp.hash_value()) >= span{
+                break;
+            }
+            temp = next_of(&temp);
+            if Rc::ptr_eq(&temp, &start){
+                break;
+            }
+        }
+        found.sort_by_key(|(hash_value, _)| self.distance(a, *hash_value));
+        Ok(found)
+    }
+
+    fn store(&mut self, hash_value: u64, value: V){
+        let preference_list = self.preference_list(hash_value);
+        for replica in preference_list.iter().skip(1){
+            replica.insert_replica(hash_value, value.clone());
+        }
+        preference_list[0].insert_resource(hash_value, value);
+        let owner = preference_list[0].hash_value();
+        self.notify(|listener| listener.on_resource_added(hash_value, owner));
+    }
+
+    pub fn replication_factor(&self) -> usize{
+        self.replication_factor
+    }
+
+    pub fn collision_policy(&self) -> CollisionPolicy{
+        self.collision_policy
+    }
+
+    pub fn set_collision_policy(&mut self, collision_policy: CollisionPolicy){
+        self.collision_policy = collision_policy;
+    }
+
+    /// Keeps every resource on its owner plus the next `replication_factor - 1`
+    /// distinct physical successors. Existing resources are re-replicated.
+    pub fn set_replication_factor(&mut self, replication_factor: usize){
+        self.replication_factor = replication_factor.max(1);
+        self.repair_replicas();
+    }
+
+    /// Preference list for `hash_value`: the owning token first, then the first
+    /// token of each following physical node, until `replication_factor`
+    /// distinct physical nodes are collected or the ring runs out of them.
+    pub fn replicas_for(&self, hash_value: u64) -> Result<Vec<NodeRef<V>>, RingError>{
+        self.check_lookup(hash_value)?;
+        Ok(self.preference_list(hash_value))
+    }
+
+    // The owner, then successors on other physical nodes. The walk first only
+    // takes successors in a zone not used yet, then in an unused rack, and only
+    // then any other physical node, so domains are spread as far as the ring
+    // allows.
+    fn preference_list(&self, hash_value: u64) -> Vec<NodeRef<V>>{
+        let owner = self.find_successor(hash_value);
+        let mut preference_list = vec![owner.clone()];
+        let mut used = vec![owner.location()];
+        let same_domain: [fn(&Location, &Location) -> bool; 3] = [
+            |a, b| a.zone == b.zone,
+            |a, b| a == b,
+            |_, _| false,
+        ];
+        for same_domain in same_domain{
+            let mut temp = next_of(&owner);
+            while preference_list.len() < self.replication_factor && !Rc::ptr_eq(&temp, &owner){
+                let location = temp.location();
+                if preference_list.iter().all(|node| node.physical_id() != temp.physical_id())
+                    && used.iter().all(|used| !same_domain(used, &location)){
+                    preference_list.push(temp.clone());
+                    used.push(location);
                 }
+                temp = next_of(&temp);
+            }
+        }
+        preference_list
+    }
 
-                if !found{
-                    while self.distance(temp.hash_value(), hash_value) >
-                        self.distance(temp.next().hash_value(), hash_value){
-                        // temp = temp.nesxt;``
-                        temp = temp.next();
-                        if temp.hash_value() == hash_value{
-                            return temp
+    /// Keys with more than one copy in the same zone, sorted by key. With
+    /// enough zones on the ring this stays empty.
+    pub fn colocation_report(&self) -> Vec<Colocation>{
+        let mut report = vec![];
+        for node in self.nodes(){
+            for hash_value in node.resources().into_keys(){
+                let preference_list = self.preference_list(hash_value);
+                let locations = preference_list.iter().map(|node| node.location()).collect::<Vec<Location>>();
+                let mut zones = vec![];
+                let mut same_rack = false;
+                for (i, a) in locations.iter().enumerate(){
+                    for b in locations.iter().skip(i + 1){
+                        if a.zone == b.zone && !zones.contains(&a.zone){
+                            zones.push(a.zone.clone());
                         }
+                        same_rack |= a == b;
                     }
-                    return temp.next();
+                }
+                if !zones.is_empty(){
+                    zones.sort();
+                    let holders = preference_list.iter().map(|node| node.physical_id()).collect();
+                    report.push(Colocation { key: hash_value, holders, zones, same_rack });
                 }
             }
-        }else{
-            panic!("Hash value out of range");
         }
+        report.sort_by_key(|colocation| colocation.key);
+        report
     }
 
-    pub fn add_resource(&mut self, hash_value: u64){
-        if self.is_in_legal_range(hash_value){
-            let target_node = self.chord_lookup(hash_value);
-            let target_node_hash = target_node.hash_value();
-            target_node.insert(hash_value, hash_value);
+    /// Rebuilds every replica from the primaries after a membership change.
+    fn repair_replicas(&mut self){
+        let nodes = self.nodes();
+        for node in nodes.iter(){
+            node.clear_replicas();
+        }
+        if self.replication_factor == 1{
+            return;
+        }
+        for node in nodes.iter(){
+            for (hash_value, value) in node.resources(){
+                for replica in self.preference_list(hash_value).iter().skip(1){
+                    replica.insert_replica(hash_value, value.clone());
+                }
+            }
         }
     }
 
 
-    fn move_resources(&mut self, dest: NodeRef, orig: NodeRef, delete_true: bool){
+    fn move_resources(&mut self, dest: NodeRef<V>, orig: NodeRef<V>, delete_true: bool) -> Vec<ResourceMove>{
         let mut delete_list = vec![];
         for (i, j) in orig.resources().iter(){
             if self.distance(*i, dest.hash_value()) < self.distance(*i, orig.hash_value()) || delete_true{
-                dest.insert_resource(*i, *j);
+                dest.insert_resource(*i, j.clone());
                 delete_list.push(*i);
             }
         }
 
         for i in delete_list.iter(){
             orig.remove_resource(*i);
+            self.notify(|listener| listener.on_resource_moved(*i, orig.hash_value(), dest.hash_value()));
         }
+        delete_list.into_iter()
+            .map(|key| ResourceMove { key, from: orig.hash_value(), to: dest.hash_value() })
+            .collect()
     }
 
-    fn add_node(&mut self, new_node: NodeRef){
-        if self.is_in_legal_range(new_node.hash_value()){
-            if self.head.is_none(){
-                new_node.set_next(new_node.clone());
-                new_node.set_previous(new_node.clone());
-                self.head = Some(new_node.clone());
-            }else{
-                let temp = self.chord_lookup(new_node.hash_value());
-                new_node.set_next(temp.clone());
-                new_node.set_previous(temp.previous());
-                new_node.next().set_previous(new_node.clone());
-                new_node.previous().set_next(new_node.clone());
-
-                self.move_resources(new_node.clone(), new_node.next(),false);
-                if new_node.hash_value() < self.head().hash_value(){
-                    self.head = Some(new_node);
-                }
-            }
+    /// Splices `new_node` into the ring and returns the resources it took over
+    /// from its successor. Replica copies are rebuilt but not reported.
+    ///
+    /// A hash that is already taken is handled by the `CollisionPolicy`, so a
+    /// position is never owned by more than one node.
+    pub fn add_node(&mut self, new_node: NodeRef<V>) -> Result<Vec<ResourceMove>, RingError>{
+        let moves = self.join(new_node)?;
+        if self.replication_factor > 1{
+            self.repair_replicas();
         }
+        Ok(moves)
     }
 
-    pub fn print_hash_ring(&self){
-        println!("****");
+    // `add_node` without the replica repair, so physical nodes repair once
+    fn join(&mut self, new_node: NodeRef<V>) -> Result<Vec<ResourceMove>, RingError>{
+        let mut hash_value = new_node.hash_value();
+        if !self.is_in_legal_range(hash_value){
+            return Err(RingError::OutOfRange(hash_value));
+        }
+        if let Ok(existing) = self.exact_node(hash_value){
+            // whatever the policy, a node cannot collide with itself
+            if Rc::ptr_eq(&existing, &new_node){
+                return Err(RingError::DuplicateNode(hash_value));
+            }
+            match self.collision_policy{
+                CollisionPolicy::Reject => return Err(RingError::DuplicateNode(hash_value)),
+                CollisionPolicy::Replace => {
+                    // a standalone node may only take the place of its own physical id
+                    let physical_id = new_node.physical_id();
+                    if new_node.as_ref().borrow().standalone && self.physical_nodes.contains_key(&physical_id)
+                        && self.physical_nodes[&physical_id] != [hash_value]{
+                        return Err(RingError::DuplicateNode(physical_id));
+                    }
+                    return Ok(self.replace_node(existing, new_node));
+                },
+                CollisionPolicy::Rehash => {
+                    hash_value = self.rehash(hash_value, &[])?;
+                    let mut node = new_node.as_ref().borrow_mut();
+                    node.hash_value = hash_value;
+                    if node.standalone{
+                        // otherwise it would count as part of the node it collided with
+                        node.physical_id = hash_value;
+                    }
+                },
+            }
+        }
+        if new_node.as_ref().borrow().standalone && self.physical_nodes.contains_key(&new_node.physical_id()){
+            return Err(RingError::DuplicateNode(new_node.physical_id()));
+        }
+        let mut moves = vec![];
         if self.head.is_none(){
-            println!("Empty hash ring");
-            return;
+            new_node.set_next(new_node.clone());
+            new_node.set_previous(new_node.clone());
+            self.head = Some(new_node.clone());
+            self.notify(|listener| listener.on_node_joined(hash_value));
+        }else{
+            let temp = self.find_successor(hash_value);
+            if temp.hash_value() == hash_value{
+                return Err(RingError::DuplicateNode(hash_value));
+            }
+            new_node.set_next(temp.clone());
+            new_node.set_previous(previous_of(&temp));
+            temp.set_previous(new_node.clone());
+            previous_of(&new_node).set_next(new_node.clone());
+            self.notify(|listener| listener.on_node_joined(hash_value));
+
+            moves = self.move_resources(new_node.clone(), temp, false);
+            if hash_value < self.head_node().hash_value(){
+                self.head = Some(new_node.clone());
+            }
         }
+        self.physical_nodes.entry(new_node.physical_id()).or_default().push(hash_value);
+        Ok(moves)
+    }
 
-        let mut temp = self.head();
-        loop{
-            {
-                let node = temp.clone();
-                println!("Node hash value: {}", node.hash_value());
-                println!("Resources: {:?}", node.resources().keys().collect::<Vec<&u64>>());
-
-                // print finger table
-                println!("Finger table: {:?}", node.inspect_finger_table() )
+    // first position among hash(hash_value, salt) for salt = 1, 2, ... that is
+    // neither on the ring nor in `pending`
+    fn rehash(&self, hash_value: u64, pending: &[u64]) -> Result<u64, RingError>{
+        for salt in 1..=MAX_REHASH_SALTS{
+            let token = self.hasher.hash(&(hash_value, salt));
+            if self.exact_node(token).is_err() && !pending.contains(&token){
+                return Ok(token);
             }
+        }
+        Err(RingError::DuplicateNode(hash_value))
+    }
 
-
-            temp = temp.next();
-            if temp.hash_value() == self.head().hash_value(){
-                break;
+    /// Puts `new_node` in the place of `old`, which leaves the ring. All of
+    /// `old`'s resources change hands without changing token, so every move
+    /// reports the same token as `from` and `to`.
+    fn replace_node(&mut self, old: NodeRef<V>, new_node: NodeRef<V>) -> Vec<ResourceMove>{
+        let hash_value = old.hash_value();
+        self.unregister_token(old.physical_id(), hash_value);
+        self.physical_nodes.entry(new_node.physical_id()).or_default().push(hash_value);
+
+        if Rc::ptr_eq(&next_of(&old), &old){
+            new_node.set_next(new_node.clone());
+            new_node.set_previous(new_node.clone());
+        }else{
+            let successor = next_of(&old);
+            let predecessor = previous_of(&old);
+            new_node.set_next(successor.clone());
+            new_node.set_previous(predecessor.clone());
+            successor.set_previous(new_node.clone());
+            predecessor.set_next(new_node.clone());
+        }
+        if Rc::ptr_eq(&old, &self.head_node()){
+            self.head = Some(new_node.clone());
+        }
+        for (range, finger) in old.finger_table(){
+            new_node.set_finger(range, finger);
+        }
+        for node in self.nodes(){
+            for (range, finger) in node.finger_table().iter(){
+                if Rc::ptr_eq(finger, &old){
+                    node.set_finger(*range, new_node.clone());
+                }
             }
         }
-        println!("****");
-    }
-    fn build_finger_tables(&mut self){
-        if self.head.is_none(){
-            return;
+        self.notify(|listener| listener.on_node_left(hash_value));
+        self.notify(|listener| listener.on_node_joined(hash_value));
+
+        let mut moves = vec![];
+        for (key, value) in old.resources(){
+            new_node.insert_resource(key, value);
+            self.notify(|listener| listener.on_resource_moved(key, hash_value, hash_value));
+            moves.push(ResourceMove { key, from: hash_value, to: hash_value });
         }
+        moves.sort_by_key(|resource_move| resource_move.key);
+        old.as_ref().borrow_mut().resources.clear();
+        old.unlink();
+        moves
+    }
 
-        let finger_ranges = self.finger_ranges();
-        let mut temp = self.head();
-        loop{
-            for (i, range) in finger_ranges.iter().enumerate(){
-                let finger_hash = (temp.hash_value() + range - 1) % (2u64.pow(self.k));
-                let finger_node = self.chord_lookup(finger_hash);
-                temp.set_finger(*range, finger_node);
+    fn unregister_token(&mut self, physical_id: u64, hash_value: u64){
+        if let Some(tokens) = self.physical_nodes.get_mut(&physical_id){
+            tokens.retain(|token| *token != hash_value);
+            if tokens.is_empty(){
+                self.physical_nodes.remove(&physical_id);
             }
+        }
+    }
 
-            temp = temp.next();
-            if temp.hash_value() == self.head().hash_value(){
-                break;
-            }
+    /// What `add_node` would do with `new_node`, collision policy included:
+    /// the resources changing owner and the replica copies created or
+    /// dropped. The ring is left as it is.
+    pub fn plan_add_node(&self, new_node: &NodeRef<V>) -> Result<MigrationPlan, RingError>{
+        // `join` would see the node colliding with itself; the copy would not
+        if self.exact_node(new_node.hash_value()).is_ok_and(|existing| Rc::ptr_eq(&existing, new_node)){
+            return Err(RingError::DuplicateNode(new_node.hash_value()));
         }
+        let joining = detached_copy(new_node);
+        let mut next = self.topology_copy();
+        let planned = next.join(joining.clone());
+        let plan = planned.map(|_| self.plan_against(&next, MembershipChange::Join(joining.hash_value()), &joining));
+        next.dismantle();
+        plan
     }
 
-}
+    /// What `remove_node` would do for the node at `hash_value`.
+    pub fn plan_remove_node(&self, hash_value: u64) -> Result<MigrationPlan, RingError>{
+        let mut next = self.topology_copy();
+        let planned = next.leave(hash_value);
+        let plan = planned.map(|leaving| self.plan_against(&next, MembershipChange::Leave(hash_value), &leaving));
+        next.dismantle();
+        plan
+    }
 
-#[cfg(test)]
-mod tests{
-    use super::*;
+    // owner and replica changes of every resource between this ring and `next`
+    fn plan_against(&self, next: &HashRing<V>, change: MembershipChange, node: &NodeRef<V>) -> MigrationPlan{
+        let mut plan = MigrationPlan {
+            change, physical_id: node.physical_id(), location: node.location(),
+            moves: vec![], replicas_added: vec![], replicas_dropped: vec![],
+            standalone: node.as_ref().borrow().standalone,
+        };
+        if next.head.is_none(){
+            // the last node leaves, nobody is left to take over
+            return plan;
+        }
+        // under `Replace` the token stays but changes hands
+        let replaced = match change{
+            MembershipChange::Join(hash_value) => self.exact_node(hash_value).ok().map(|_| hash_value),
+            MembershipChange::Leave(_) => None,
+        };
+        let holders = |ring: &HashRing<V>, key: u64| ring.preference_list(key).iter().skip(1)
+            .map(|node| (node.hash_value(), node.physical_id()))
+            .collect::<Vec<(u64, u64)>>();
+        for owner in self.nodes(){
+            for key in owner.resources().into_keys(){
+                let to = next.find_successor(key).hash_value();
+                if to != owner.hash_value() || replaced == Some(to){
+                    plan.moves.push(ResourceMove { key, from: owner.hash_value(), to });
+                }
+                if self.replication_factor > 1{
+                    let (before, after) = (holders(self, key), holders(next, key));
+                    for (node, _) in after.iter().filter(|holder| !before.contains(holder)){
+                        plan.replicas_added.push(ReplicaCopy { key, node: *node });
+                    }
+                    for (node, _) in before.iter().filter(|holder| !after.contains(holder)){
+                        plan.replicas_dropped.push(ReplicaCopy { key, node: *node });
+                    }
+                }
+            }
+        }
+        plan.moves.sort_by_key(|resource_move| resource_move.key);
+        plan.replicas_added.sort_by_key(|copy| (copy.key, copy.node));
+        plan.replicas_dropped.sort_by_key(|copy| (copy.key, copy.node));
+        plan
+    }
 
-    #[test]
-    fn test_distance() {
-        let ring = HashRing::new(5);
-        assert_eq!(8, ring.distance(29, 5));
-        assert_eq!(15, ring.distance(29, 12));
-        assert_eq!(24, ring.distance(5, 29));
+    // same tokens, physical nodes, fingers and settings, without resources,
+    // replicas or listeners; plans try a change out on it
+    fn topology_copy(&self) -> HashRing<V>{
+        let mut copy = HashRing::with_seed(self.k, self.vnodes_per_weight, self.hasher.seed());
+        copy.replication_factor = self.replication_factor;
+        copy.collision_policy = self.collision_policy;
+        copy.physical_nodes = self.physical_nodes.clone();
+        let nodes = self.nodes();
+        let copies = nodes.iter().map(detached_copy).collect::<Vec<NodeRef<V>>>();
+        let by_hash = copies.iter().map(|node| (node.hash_value(), node.clone())).collect::<HashMap<u64, NodeRef<V>>>();
+        for (i, node) in copies.iter().enumerate(){
+            node.set_next(copies[(i + 1) % copies.len()].clone());
+            node.set_previous(copies[(i + copies.len() - 1) % copies.len()].clone());
+            for (range, finger) in nodes[i].finger_table(){
+                node.set_finger(range, by_hash[&finger.hash_value()].clone());
+            }
+        }
+        copy.head = copies.first().cloned();
+        copy
     }
 
-    #[test]
-    fn test_scenario(){
-        // stdout used
-        let mut hr = HashRing::new(5);
-        hr.add_node(RefCell::new(Node::new(12)).into());
-        hr.add_node(RefCell::new(Node::new(18)).into());
-        hr.add_resource(24);
-        hr.add_resource(21);
-        hr.add_resource(16);
-        hr.add_resource(23);
-        hr.add_resource(2);
-        hr.add_resource(29);
-        hr.add_resource(28);
-        hr.add_resource(7);
-        hr.add_resource(10);
-        hr.print_hash_ring();
+    // breaks the reference cycles of a ring that is no longer needed
+    fn dismantle(self){
+        for node in self.nodes(){
+            node.unlink();
+        }
+    }
 
-        hr.add_node(RefCell::new(Node::new(5)).into());
-        hr.add_node(RefCell::new(Node::new(27)).into());
-        hr.add_node(RefCell::new(Node::new(30)).into());
+    /// Executes `plan` if it still describes what the change would do. A plan
+    /// that went stale (resources or nodes changed since it was computed) is
+    /// refused and the ring is left untouched. A joining node gets the
+    /// physical id and location the plan was made for.
+    pub fn apply_plan(&mut self, plan: &MigrationPlan) -> Result<(), RingError>{
+        let current = match plan.change{
+            MembershipChange::Join(hash_value) => self.plan_add_node(&plan.joining_node(hash_value)),
+            MembershipChange::Leave(hash_value) => self.plan_remove_node(hash_value),
+        };
+        if current.as_ref() != Ok(plan){
+            return Err(RingError::StalePlan);
+        }
+        match plan.change{
+            MembershipChange::Join(hash_value) => self.add_node(plan.joining_node(hash_value)).map(|_| ()),
+            MembershipChange::Leave(hash_value) => self.remove_node(hash_value).map(|_| ()),
+        }
+    }
 
-        hr.build_finger_tables();
-        hr.print_hash_ring();
+    // the node sitting exactly at `hash_value`
+    fn exact_node(&self, hash_value: u64) -> Result<NodeRef<V>, RingError>{
+        if !self.is_in_legal_range(hash_value){
+            return Err(RingError::OutOfRange(hash_value));
+        }
+        if self.head.is_none(){
+            return Err(RingError::UnknownNode(hash_value));
+        }
+        let node = self.find_successor(hash_value);
+        if node.hash_value() != hash_value{
+            return Err(RingError::UnknownNode(hash_value));
+        }
+        Ok(node)
     }
 
-    #[test]
-    fn performance_test(){
-        let start= std::time::Instant::now();
-        let mut hr = HashRing::new(20);
-        let max = hr.max;
+    /// Takes the node at `hash_value` out of the ring and hands all of its
+    /// resources over to its successor. Finger entries of the remaining nodes
+    /// that still point at the departed node are redirected to that successor,
+    /// which is where successor(n + 2^i) lands once the node is gone.
+    pub fn remove_node(&mut self, hash_value: u64) -> Result<NodeRef<V>, RingError>{
+        let node = self.leave(hash_value)?;
+        if self.replication_factor > 1{
+            self.repair_replicas();
+        }
+        Ok(node)
+    }
 
-        for i in (0..5000){
-            let random_value: u64 = rand::random::<u64>() % max;
-            hr.add_node(RefCell::new(Node::new(random_value)).into());            
+    // `remove_node` without the replica repair
+    fn leave(&mut self, hash_value: u64) -> Result<NodeRef<V>, RingError>{
+        let node = self.exact_node(hash_value)?;
+        self.unregister_token(node.physical_id(), hash_value);
+
+        let successor = next_of(&node);
+        if Rc::ptr_eq(&successor, &node){
+            // the last node leaves, nobody is left to take over its resources
+            self.head = None;
+            node.unlink();
+            self.notify(|listener| listener.on_node_left(hash_value));
+            return Ok(node);
         }
 
-        hr.build_finger_tables();
+        let predecessor = previous_of(&node);
+        predecessor.set_next(successor.clone());
+        successor.set_previous(predecessor.clone());
+        self.notify(|listener| listener.on_node_left(hash_value));
 
-        for i in (0..50000){
-            let random_value: u64 = rand::random::<u64>() % max;
-            hr.add_resource(random_value);            
+        self.move_resources(successor.clone(), node.clone(), true);
+        if Rc::ptr_eq(&node, &self.head_node()){
+            // head is the minimum, so its successor is the next smallest token
+            self.head = Some(successor.clone());
         }
 
-        let duration = start.elapsed();
-        println!("Time elapsed in expensive_function() is: {:?}", duration);
+        for temp in self.nodes(){
+            for (range, finger) in temp.finger_table().iter(){
+                if Rc::ptr_eq(finger, &node){
+                    temp.set_finger(*range, successor.clone());
+                }
+            }
+        }
 
+        node.unlink();
+        Ok(node)
     }
 
+    fn vnode_token(&self, physical_id: u64, index: u32) -> u64{
+        self.hash_key(&(physical_id, index))
+    }
+
+    /// Places `weight * vnodes_per_weight` tokens for `physical_id` on the ring
+    /// and returns them. Tokens that would land on an occupied position go
+    /// through the collision policy. Every position is worked out before the
+    /// ring changes, so if the policy refuses one nothing is placed.
+    pub fn add_physical_node(&mut self, physical_id: u64, weight: u32) -> Result<Vec<u64>, RingError>{
+        self.add_physical_node_at(physical_id, weight, Location::default())
+    }
 
-}
\ No newline at end of file
+    /// `add_physical_node` with every token labelled with `location`.
+    pub fn add_physical_node_at(&mut self, physical_id: u64, weight: u32, location: Location) -> Result<Vec<u64>, RingError>{
+        let count = weight.checked_mul(self.vnodes_per_weight).ok_or(RingError::TooManyTokens(weight))?;
+        if self.physical_nodes.contains_key(&physical_id){
+            return Err(RingError::DuplicateNode(physical_id));
+        }
+        let mut tokens: Vec<u64> = vec![];
+        for index in 0..count{
+            let token = self.vnode_token(physical_id, index);
+            let taken = self.exact_node(token).is_ok() || tokens.contains(&token);
+            tokens.push(match self.collision_policy{
+                _ if !taken => token,
+                // a token of this node cannot replace another one of its own
+                CollisionPolicy::Replace if !tokens.contains(&token) => token,
+                CollisionPolicy::Rehash => self.rehash(token, &tokens)?,
+                _ => return Err(RingError::DuplicateNode(token)),
+            });
+        }
+        for token in tokens.iter(){
+            let node = Node::new_virtual(*token, physical_id).with_location(location.clone());
+            self.join(RefCell::new(node).into()).expect("token positions were checked");
+        }
+        if self.replication_factor > 1{
+            self.repair_replicas();
+        }
+        Ok(tokens)
+    }
+
+    /// Removes every token of `physical_id`, handing each token's resources to
+    /// its successor. Nothing is removed unless every token is on the ring.
+    pub fn remove_physical_node(&mut self, physical_id: u64) -> Result<(), RingError>{
+        let tokens = self.physical_nodes.get(&physical_id).cloned().ok_or(RingError::UnknownNode(physical_id))?;
+        for token in tokens.iter(){
+            if self.exact_node(*token)?.physical_id() != physical_id{
+                return Err(RingError::UnknownNode(*token));
+            }
+        }
+        for token in tokens{
+            self.leave(token).expect("token was checked to be on the ring");
+        }
+        if self.replication_factor > 1{
+            self.repair_replicas();
+        }
+        Ok(())
+    }
+
+    /// Adds a physical node identified by `name` (e.g. "host:port"), hashed
+    /// the same way as keys. Returns the physical id.
+    pub fn add_named_node(&mut self, name: &str, weight: u32) -> Result<u64, RingError>{
+        let physical_id = self.hash_key(name);
+        self.add_physical_node(physical_id, weight)?;
+        Ok(physical_id)
+    }
+
+    pub fn physical_nodes(&self) -> Vec<u64>{
+        let mut ids = self.physical_nodes.keys().copied().collect::<Vec<u64>>();
+        ids.sort();
+        ids
+    }
+
+    pub fn tokens_of(&self, physical_id: u64) -> Vec<u64>{
+        self.physical_nodes.get(&physical_id).cloned().unwrap_or_default()
+    }
+
+    /// Per physical node resource counts and owned arcs, plus summary figures
+    /// over the resource counts. Replicas are not counted.
+    pub fn stats(&self) -> RingStats{
+        let mut per_node: HashMap<u64, NodeStats> = HashMap::new();
+        let nodes = self.nodes();
+        let space = 2f64.powi(self.k as i32);
+        for node in nodes.iter(){
+            let arc = if nodes.len() == 1{
+                space
+            }else{
+                self.distance(previous_of(node).hash_value(), node.hash_value()) as f64
+            };
+            let entry = per_node.entry(node.physical_id()).or_insert(NodeStats {
+                physical_id: node.physical_id(), tokens: 0, resources: 0, ownership: 0.0,
+            });
+            entry.tokens += 1;
+            entry.resources += node.resources().len();
+            entry.ownership += arc / space;
+        }
+
+        let mut nodes = per_node.into_values().collect::<Vec<NodeStats>>();
+        nodes.sort_by_key(|node| node.physical_id);
+        let total_resources = nodes.iter().map(|node| node.resources).sum::<usize>();
+        let count = nodes.len() as f64;
+        let mean = if nodes.is_empty(){ 0.0 } else { total_resources as f64 / count };
+        let stddev = if nodes.is_empty(){
+            0.0
+        }else{
+            (nodes.iter().map(|node| (node.resources as f64 - mean).powi(2)).sum::<f64>() / count).sqrt()
+        };
+        let max = nodes.iter().map(|node| node.resources).max().unwrap_or(0);
+        let max_over_mean = if mean > 0.0{ max as f64 / mean } else { 0.0 };
+
+        let mut loads = nodes.iter().map(|node| node.resources as f64).collect::<Vec<f64>>();
+        loads.sort_by(f64::total_cmp);
+        let gini = if total_resources == 0{
+            0.0
+        }else{
+            let weighted = loads.iter().enumerate().map(|(i, load)| (i + 1) as f64 * load).sum::<f64>();
+            2.0 * weighted / (count * total_resources as f64) - (count + 1.0) / count
+        };
+
+        RingStats { nodes, total_resources, mean, stddev, max_over_mean, gini }
+    }
+
+    /// Point at `radius` from the centre for `hash_value`; 0 sits at twelve
+    /// o'clock and hash values grow clockwise.
+    fn position(&self, hash_value: u64, radius: f64) -> (f64, f64){
+        let angle = 2.0 * PI * hash_value as f64 / 2f64.powi(self.k as i32);
+        (radius * angle.sin(), -radius * angle.cos())
+    }
+
+    // finger targets of `node` other than itself, each once
+    fn finger_targets(node: &NodeRef<V>) -> Vec<u64>{
+        let mut targets = node.inspect_finger_table().into_iter()
+            .map(|(_, finger)| finger)
+            .filter(|finger| *finger != node.hash_value())
+            .collect::<Vec<u64>>();
+        targets.sort();
+        targets.dedup();
+        targets
+    }
+
+    /// Graphviz rendering for `neato -n`: tokens pinned at their angle on the
+    /// ring, successor edges in bold and finger edges dashed.
+    pub fn to_dot(&self) -> String{
+        let mut dot = String::from("digraph ring {\n    node [shape=circle];\n");
+        let nodes = self.nodes();
+        for node in nodes.iter(){
+            let (x, y) = self.position(node.hash_value(), 300.0);
+            writeln!(dot, "    n{} [label=\"{}\\n{} resources\", pos=\"{:.1},{:.1}!\"];",
+                node.hash_value(), node.hash_value(), node.resources().len(), x, -y).unwrap();
+        }
+        for node in nodes.iter(){
+            writeln!(dot, "    n{} -> n{} [style=bold];", node.hash_value(), next_of(node).hash_value()).unwrap();
+            for finger in Self::finger_targets(node){
+                writeln!(dot, "    n{} -> n{} [style=dashed, color=steelblue];", node.hash_value(), finger).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Standalone SVG of the ring: the arc each token owns is shaded, every
+    /// resource is a tick on the circle and finger edges are drawn as chords.
+    pub fn to_svg(&self) -> String{
+        const SIZE: f64 = 400.0;
+        const RADIUS: f64 = 160.0;
+        let mut svg = String::new();
+        writeln!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{0}\" viewBox=\"{1} {1} {0} {0}\">",
+            SIZE, -SIZE / 2.0).unwrap();
+        writeln!(svg, "  <circle cx=\"0\" cy=\"0\" r=\"{}\" fill=\"none\" stroke=\"#ccc\"/>", RADIUS).unwrap();
+
+        let nodes = self.nodes();
+        for (index, node) in nodes.iter().enumerate(){
+            // a token owns the arc from its predecessor (exclusive) up to itself
+            let shade = if index % 2 == 0 { "#9ecae1" } else { "#c6dbef" };
+            let from = previous_of(node).hash_value();
+            let arc = self.distance(from, node.hash_value());
+            if arc == 0{
+                writeln!(svg, "  <circle class=\"arc\" cx=\"0\" cy=\"0\" r=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"12\"/>",
+                    RADIUS, shade).unwrap();
+            }else{
+                let (x1, y1) = self.position(from, RADIUS);
+                let (x2, y2) = self.position(node.hash_value(), RADIUS);
+                let large = if arc > self.max / 2 { 1 } else { 0 };
+                writeln!(svg, "  <path class=\"arc\" d=\"M {:.2} {:.2} A {} {} 0 {} 1 {:.2} {:.2}\" fill=\"none\" stroke=\"{}\" stroke-width=\"12\"/>",
+                    x1, y1, RADIUS, RADIUS, large, x2, y2, shade).unwrap();
+            }
+        }
+        for node in nodes.iter(){
+            let mut resources = node.resources().into_keys().collect::<Vec<u64>>();
+            resources.sort();
+            for hash_value in resources{
+                let (x1, y1) = self.position(hash_value, RADIUS - 8.0);
+                let (x2, y2) = self.position(hash_value, RADIUS + 8.0);
+                writeln!(svg, "  <line class=\"resource\" x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"#333\"/>",
+                    x1, y1, x2, y2).unwrap();
+            }
+        }
+        for node in nodes.iter(){
+            let (x1, y1) = self.position(node.hash_value(), RADIUS);
+            for finger in Self::finger_targets(node){
+                let (x2, y2) = self.position(finger, RADIUS);
+                writeln!(svg, "  <line class=\"finger\" x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"steelblue\" stroke-dasharray=\"4 3\"/>",
+                    x1, y1, x2, y2).unwrap();
+            }
+        }
+        for node in nodes.iter(){
+            let (x, y) = self.position(node.hash_value(), RADIUS);
+            let (label_x, label_y) = self.position(node.hash_value(), RADIUS + 24.0);
+            writeln!(svg, "  <circle class=\"node\" cx=\"{:.2}\" cy=\"{:.2}\" r=\"5\" fill=\"#08519c\"/>", x, y).unwrap();
+            writeln!(svg, "  <text x=\"{:.2}\" y=\"{:.2}\" font-size=\"11\" text-anchor=\"middle\">{}</text>",
+                label_x, label_y, node.hash_value()).unwrap();
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    pub fn print_hash_ring(&self){
+        println!("****");
+        if self.head.is_none(){
+            println!("Empty hash ring");
+            return;
+        }
+
+        let mut temp = self.head_node();
+        loop{
+            {
+                let node = temp.clone();
+                println!("Node hash value: {} (physical node {})", node.hash_value(), node.physical_id());
+                println!("Resources: {:?}", node.resources().keys().collect::<Vec<&u64>>());
+                println!("Replicas: {:?}", node.replicas().keys().collect::<Vec<&u64>>());
+
+                // print finger table
+                println!("Finger table: {:?}", node.inspect_finger_table() )
+            }
+
+
+            temp = next_of(&temp);
+            if temp.hash_value() == self.head_node().hash_value(){
+                break;
+            }
+        }
+        println!("****");
+    }
+    /// Checks the links, token order, head, resource placement and finger
+    /// tables, returning every violation found. Fingers are only maintained by
+    /// `build_finger_tables`, so they go stale when membership changes.
+    pub fn validate(&self) -> Vec<Violation>{
+        let mut violations = vec![];
+        let Some(head) = self.head.clone() else{
+            return violations;
+        };
+        let registered = self.physical_nodes.values().map(Vec::len).sum::<usize>();
+
+        let mut nodes = vec![];
+        let mut temp = head.clone();
+        loop{
+            nodes.push(temp.clone());
+            let Some(next) = temp.next() else{
+                break;
+            };
+            if !next.previous().is_some_and(|previous| Rc::ptr_eq(&previous, &temp)){
+                violations.push(Violation::BrokenLink { node: temp.hash_value(), next: next.hash_value() });
+            }
+            if !Rc::ptr_eq(&next, &head) && next.hash_value() <= temp.hash_value(){
+                violations.push(Violation::OutOfOrder { node: temp.hash_value(), next: next.hash_value() });
+            }
+            temp = next;
+            if Rc::ptr_eq(&temp, &head) || nodes.len() > registered{
+                break;
+            }
+        }
+        if nodes.len() != registered || !Rc::ptr_eq(&temp, &head){
+            violations.push(Violation::TokenCount { registered, linked: nodes.len() });
+        }
+
+        let mut tokens = nodes.iter().map(|node| node.hash_value()).collect::<Vec<u64>>();
+        tokens.sort();
+        if tokens[0] != head.hash_value(){
+            violations.push(Violation::HeadNotMinimum { head: head.hash_value(), minimum: tokens[0] });
+        }
+        // first token at or after `hash_value`, wrapping past the top
+        let successor = |hash_value: u64| *tokens.get(tokens.partition_point(|token| *token < hash_value)).unwrap_or(&tokens[0]);
+
+        for node in nodes.iter(){
+            let physical_id = node.physical_id();
+            if !self.physical_nodes.get(&physical_id).is_some_and(|tokens| tokens.contains(&node.hash_value())){
+                violations.push(Violation::UnregisteredToken { node: node.hash_value(), physical_id });
+            }
+            let mut keys = node.resources().into_keys().collect::<Vec<u64>>();
+            keys.sort();
+            for key in keys{
+                let owner = successor(key);
+                if owner != node.hash_value(){
+                    violations.push(Violation::MisplacedResource { key, node: node.hash_value(), owner });
+                }
+            }
+            let mut fingers = node.inspect_finger_table();
+            fingers.sort();
+            for (range, finger) in fingers{
+                let expected = successor(node.hash_value().wrapping_add(range) & self.max);
+                if finger != expected{
+                    violations.push(Violation::WrongFinger { node: node.hash_value(), range, finger, expected });
+                }
+            }
+        }
+        violations
+    }
+
+    pub fn build_finger_tables(&mut self){
+        if self.head.is_none(){
+            return;
+        }
+
+        let finger_ranges = self.finger_ranges();
+        let mut temp = self.head_node();
+        loop{
+            for range in finger_ranges.iter(){
+                let finger_hash = temp.hash_value().wrapping_add(*range) & self.max;
+                let finger_node = self.find_successor(finger_hash);
+                temp.set_finger(*range, finger_node);
+            }
+
+            temp = next_of(&temp);
+            if temp.hash_value() == self.head_node().hash_value(){
+                break;
+            }
+        }
+        self.notify(|listener| listener.on_finger_table_rebuilt());
+    }
+
+}
+
+/// A ring storing key-value pairs. Each position holds the entries of every
+/// key hashing to it, so keys that collide keep their own values.
+pub type KeyValueRing<K, V> = HashRing<Vec<(K, V)>>;
+
+impl<K: Eq + Hash + Clone, V: Clone> HashRing<Vec<(K, V)>>{
+    /// Stores `value` under `key` on the node `chord_lookup` picks for the
+    /// key's hash, replacing the value of an equal key. Returns the hash.
+    pub fn put(&mut self, key: K, value: V) -> Result<u64, RingError>{
+        let hash_value = self.hash_key(&key);
+        self.check_lookup(hash_value)?;
+        let mut entries = self.find_successor(hash_value).resource(hash_value).unwrap_or_default();
+        match entries.iter_mut().find(|(stored, _)| *stored == key){
+            Some(entry) => entry.1 = value,
+            None => entries.push((key, value)),
+        }
+        self.store(hash_value, entries);
+        Ok(hash_value)
+    }
+
+    /// Value stored under `key`, if any.
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Result<Option<V>, RingError> where K: std::borrow::Borrow<Q>{
+        let hash_value = self.hash_key(key);
+        let entries = self.chord_lookup(hash_value)?.resource(hash_value).unwrap_or_default();
+        Ok(entries.into_iter().find(|(stored, _)| stored.borrow() == key).map(|(_, value)| value))
+    }
+
+    /// Removes `key` from its owner and every replica, returning its value.
+    /// Other keys at the same position stay.
+    pub fn delete<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Result<Option<V>, RingError> where K: std::borrow::Borrow<Q>{
+        let hash_value = self.hash_key(key);
+        self.check_lookup(hash_value)?;
+        let preference_list = self.preference_list(hash_value);
+        let mut entries = preference_list[0].resource(hash_value).unwrap_or_default();
+        let Some(position) = entries.iter().position(|(stored, _)| stored.borrow() == key) else{
+            return Ok(None);
+        };
+        let (_, value) = entries.remove(position);
+        if entries.is_empty(){
+            for replica in preference_list.iter().skip(1){
+                replica.remove_replica(hash_value);
+            }
+            preference_list[0].remove_resource(hash_value);
+        }else{
+            self.store(hash_value, entries);
+        }
+        Ok(Some(value))
+    }
+}
+
+impl HashRing<u64>{
+    /// Stores a resource identified only by its hash; the value is the hash.
+    pub fn add_resource(&mut self, hash_value: u64) -> Result<(), RingError>{
+        self.check_lookup(hash_value)?;
+        self.store(hash_value, hash_value);
+        Ok(())
+    }
+
+    /// Captures tokens, resources and finger tables. Listeners are not part
+    /// of the snapshot.
+    pub fn snapshot(&self) -> RingSnapshot{
+        let nodes = self.nodes().iter()
+            .map(|node| {
+                let mut resources = node.resources().into_iter().collect::<Vec<(u64, u64)>>();
+                resources.sort();
+                let mut fingers = node.inspect_finger_table();
+                fingers.sort();
+                let Location { zone, rack } = node.location();
+                let standalone = node.as_ref().borrow().standalone;
+                NodeSnapshot { hash_value: node.hash_value(), physical_id: node.physical_id(), standalone, zone, rack, resources, fingers }
+            })
+            .collect();
+        RingSnapshot {
+            version: SNAPSHOT_VERSION,
+            k: self.k,
+            vnodes_per_weight: self.vnodes_per_weight,
+            seed: self.seed(),
+            replication_factor: self.replication_factor as u64,
+            collision_policy: self.collision_policy,
+            nodes,
+        }
+    }
+
+    /// Rebuilds a ring from `snapshot`. Resources go back onto the node they
+    /// were recorded on, so lookups match the original ring exactly; a
+    /// resource recorded on a node that does not own it is refused.
+    pub fn restore(snapshot: &RingSnapshot) -> Result<Self, SnapshotError>{
+        if snapshot.version != SNAPSHOT_VERSION{
+            return Err(SnapshotError::UnsupportedVersion(snapshot.version));
+        }
+        check_k(snapshot.k)?;
+        let mut ring = Self::with_seed(snapshot.k, snapshot.vnodes_per_weight, snapshot.seed);
+        let mut by_hash = HashMap::new();
+        for node_snapshot in snapshot.nodes.iter(){
+            let location = Location::new(&node_snapshot.zone, &node_snapshot.rack);
+            let node = Node::new_virtual(node_snapshot.hash_value, node_snapshot.physical_id).with_location(location);
+            let node: NodeRef = RefCell::new(Node { standalone: node_snapshot.standalone, ..node }).into();
+            ring.add_node(node.clone()).map_err(SnapshotError::Ring)?;
+            by_hash.insert(node_snapshot.hash_value, node);
+        }
+        for node_snapshot in snapshot.nodes.iter(){
+            let node = &by_hash[&node_snapshot.hash_value];
+            for (hash_value, value) in node_snapshot.resources.iter(){
+                if !ring.is_in_legal_range(*hash_value){
+                    return Err(SnapshotError::Ring(RingError::OutOfRange(*hash_value)));
+                }
+                node.insert_resource(*hash_value, *value);
+            }
+            for (range, finger) in node_snapshot.fingers.iter(){
+                let finger = by_hash.get(finger).ok_or(SnapshotError::UnknownFinger(*finger))?;
+                node.set_finger(*range, finger.clone());
+            }
+        }
+        for violation in ring.validate(){
+            if let Violation::MisplacedResource { key, .. } = violation{
+                return Err(SnapshotError::MisplacedResource(key));
+            }
+        }
+        // set last, so a colliding token in the snapshot is refused rather than resolved
+        ring.collision_policy = snapshot.collision_policy;
+        ring.set_replication_factor(snapshot.replication_factor as usize);
+        Ok(ring)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn test_distance() {
+        let ring: HashRing = HashRing::new(5);
+        assert_eq!(8, ring.distance(29, 5));
+        assert_eq!(15, ring.distance(29, 12));
+        assert_eq!(24, ring.distance(5, 29));
+    }
+
+    #[test]
+    fn test_scenario(){
+        // stdout used
+        let mut hr = HashRing::new(5);
+        hr.add_node(RefCell::new(Node::new(12)).into()).unwrap();
+        hr.add_node(RefCell::new(Node::new(18)).into()).unwrap();
+        hr.add_resource(24).unwrap();
+        hr.add_resource(21).unwrap();
+        hr.add_resource(16).unwrap();
+        hr.add_resource(23).unwrap();
+        hr.add_resource(2).unwrap();
+        hr.add_resource(29).unwrap();
+        hr.add_resource(28).unwrap();
+        hr.add_resource(7).unwrap();
+        hr.add_resource(10).unwrap();
+        hr.print_hash_ring();
+
+        hr.add_node(RefCell::new(Node::new(5)).into()).unwrap();
+        hr.add_node(RefCell::new(Node::new(27)).into()).unwrap();
+        hr.add_node(RefCell::new(Node::new(30)).into()).unwrap();
+
+        hr.build_finger_tables();
+        hr.print_hash_ring();
+    }
+
+    #[test]
+    fn performance_test(){
+        let start= std::time::Instant::now();
+        let mut rng = StdRng::seed_from_u64(5000);
+        let mut hr = HashRing::new(20);
+        let max = hr.max;
+
+        for _ in 0..5000{
+            let random_value: u64 = rng.random::<u64>() % max;
+            // collisions are expected with this many random tokens
+            let _ = hr.add_node(RefCell::new(Node::new(random_value)).into());
+        }
+
+        hr.build_finger_tables();
+
+        for _ in 0..50000{
+            let random_value: u64 = rng.random::<u64>() % max;
+            hr.add_resource(random_value).unwrap();            
+        }
+
+        let duration = start.elapsed();
+        println!("Time elapsed in expensive_function() is: {:?}", duration);
+
+    }
+
+    #[test]
+    fn test_physical_nodes(){
+        let mut hr = HashRing::with_vnodes(16, 8);
+        let light = hr.add_physical_node(1, 1).unwrap();
+        let heavy = hr.add_physical_node(2, 3).unwrap();
+        assert_eq!(8, light.len());
+        assert_eq!(24, heavy.len());
+        assert_eq!(vec![1, 2], hr.physical_nodes());
+        assert_eq!(Err(RingError::TooManyTokens(u32::MAX)), hr.add_physical_node(3, u32::MAX));
+        assert_eq!(Err(RingError::DuplicateNode(2)), hr.add_physical_node(2, 1));
+        // a standalone node uses its hash as physical id, which must be free
+        assert!(matches!(hr.add_node(RefCell::new(Node::new(2)).into()), Err(RingError::DuplicateNode(2))));
+        hr.add_node(RefCell::new(Node::new(3)).into()).unwrap();
+        assert_eq!(Err(RingError::DuplicateNode(3)), hr.add_physical_node(3, 1));
+        hr.remove_physical_node(3).unwrap();
+
+        // 16 tokens cannot all fit in a 4-bit space; none is placed and nobody hears of it
+        let mut full: HashRing = HashRing::with_vnodes(4, 16);
+        full.add_node(RefCell::new(Node::new(9)).into()).unwrap();
+        full.add_resource(3).unwrap();
+        let events = Rc::new(RefCell::new(vec![]));
+        full.add_listener(Box::new(Recorder(events.clone())));
+        assert!(matches!(full.add_physical_node(1, 1), Err(RingError::DuplicateNode(_))));
+        assert!(events.borrow().is_empty());
+        full.set_collision_policy(CollisionPolicy::Rehash);
+        assert!(matches!(full.add_physical_node(1, 1), Err(RingError::DuplicateNode(_))));
+        assert!(events.borrow().is_empty());
+        assert_eq!(vec![9], full.physical_nodes());
+        assert_eq!(vec![9], full.nodes().iter().map(|node| node.hash_value()).collect::<Vec<u64>>());
+        assert!(full.exact_node(9).unwrap().resources().contains_key(&3));
+
+        for hash_value in (0..hr.max).step_by(7){
+            hr.add_resource(hash_value).unwrap();
+        }
+        hr.build_finger_tables();
+        for token in heavy.iter(){
+            assert_eq!(Ok(2), hr.lookup_physical_node(*token));
+        }
+
+        // the whole physical node leaves, every resource ends up on node 1
+        assert_eq!(Ok(()), hr.remove_physical_node(2));
+        assert_eq!(Err(RingError::UnknownNode(2)), hr.remove_physical_node(2));
+        assert_eq!(vec![1], hr.physical_nodes());
+        let mut total = 0;
+        for token in hr.tokens_of(1){
+            let node = hr.chord_lookup(token).unwrap();
+            assert_eq!(1, node.physical_id());
+            total += node.resources().len();
+        }
+        assert_eq!((0..hr.max).step_by(7).count(), total);
+    }
+
+    #[test]
+    fn test_vnodes_balance_load(){
+        // share of the ring owned by the most loaded of 4 physical nodes
+        fn max_share(vnodes_per_weight: u32) -> f64{
+            let mut hr: HashRing = HashRing::with_vnodes(20, vnodes_per_weight);
+            for physical_id in 1..=4{
+                hr.add_physical_node(physical_id, 1).unwrap();
+            }
+            let mut load = HashMap::new();
+            for hash_value in (0..hr.max).step_by(97){
+                *load.entry(hr.lookup_physical_node(hash_value).unwrap()).or_insert(0) += 1;
+            }
+            let total: u64 = load.values().sum();
+            *load.values().max().unwrap() as f64 / total as f64
+        }
+
+        assert!(max_share(64) < max_share(1));
+        assert!(max_share(64) < 0.35);
+    }
+
+    #[test]
+    fn test_put_and_node_for(){
+        let mut hr: KeyValueRing<String, u64> = HashRing::with_seed(16, 16, 7);
+        let alpha = hr.add_named_node("10.0.0.1:7000", 1).unwrap();
+        let beta = hr.add_named_node("10.0.0.2:7000", 1).unwrap();
+        assert_eq!(hr.hash_key("10.0.0.1:7000"), alpha);
+        assert_eq!(vec![alpha.min(beta), alpha.max(beta)], hr.physical_nodes());
+
+        for i in 0..100u64{
+            let key = format!("user:{}", i);
+            let hash_value = hr.put(key.clone(), i).unwrap();
+            let node = hr.chord_lookup(hash_value).unwrap();
+            assert!(node.resources()[&hash_value].contains(&(key.clone(), i)));
+            assert_eq!(Ok(node.physical_id()), hr.node_for(&key));
+        }
+
+        // the same key lands elsewhere under another seed
+        let other: HashRing = HashRing::with_seed(16, 16, 8);
+        assert_ne!(hr.hash_key("user:1"), other.hash_key("user:1"));
+    }
+
+    #[test]
+    fn test_replication(){
+        // physical ids of every node holding a copy of `hash_value`
+        fn holders(hr: &HashRing, hash_value: u64) -> Vec<u64>{
+            let mut holders = hr.nodes().iter()
+                .filter(|node| node.resources().contains_key(&hash_value) || node.replicas().contains_key(&hash_value))
+                .map(|node| node.physical_id())
+                .collect::<Vec<u64>>();
+            holders.sort();
+            holders
+        }
+
+        let mut hr = HashRing::with_vnodes(16, 4);
+        hr.set_replication_factor(3);
+        for physical_id in 1..=4{
+            hr.add_physical_node(physical_id, 1).unwrap();
+        }
+        let keys = (0..hr.max).step_by(101).collect::<Vec<u64>>();
+        for hash_value in keys.iter(){
+            hr.add_resource(*hash_value).unwrap();
+        }
+
+        for hash_value in keys.iter(){
+            let mut expected = hr.replicas_for(*hash_value).unwrap().iter().map(|node| node.physical_id()).collect::<Vec<u64>>();
+            assert_eq!(3, expected.len());
+            expected.sort();
+            expected.dedup();
+            assert_eq!(3, expected.len());
+            assert_eq!(expected, holders(&hr, *hash_value));
+        }
+
+        // joins and leaves keep exactly 3 copies on distinct physical nodes
+        hr.add_physical_node(5, 1).unwrap();
+        hr.remove_physical_node(2).unwrap();
+        hr.remove_physical_node(3).unwrap();
+        for hash_value in keys.iter(){
+            let preference_list = hr.replicas_for(*hash_value).unwrap();
+            assert_eq!(preference_list[0].hash_value(), hr.chord_lookup(*hash_value).unwrap().hash_value());
+            let mut expected = preference_list.iter().map(|node| node.physical_id()).collect::<Vec<u64>>();
+            expected.sort();
+            assert_eq!(expected, holders(&hr, *hash_value));
+            assert_eq!(3, expected.len());
+        }
+
+        // fewer physical nodes than the replication factor
+        hr.remove_physical_node(4).unwrap();
+        for hash_value in keys.iter(){
+            assert_eq!(vec![1, 5], holders(&hr, *hash_value));
+        }
+    }
+
+    #[test]
+    fn test_stats(){
+        let mut hr = HashRing::new(5);
+        assert_eq!(0, hr.stats().nodes.len());
+        for hash_value in [5, 12, 18]{
+            hr.add_node(RefCell::new(Node::new(hash_value)).into()).unwrap();
+        }
+        for hash_value in [2, 7, 10, 16]{
+            hr.add_resource(hash_value).unwrap();
+        }
+
+        let stats = hr.stats();
+        let counts = stats.nodes.iter().map(|node| (node.physical_id, node.resources)).collect::<Vec<(u64, usize)>>();
+        assert_eq!(vec![(5, 1), (12, 2), (18, 1)], counts);
+        let ownership = stats.nodes.iter().map(|node| node.ownership).collect::<Vec<f64>>();
+        assert_eq!(vec![19.0 / 32.0, 7.0 / 32.0, 6.0 / 32.0], ownership);
+        assert_eq!(4, stats.total_resources);
+        assert!((stats.mean - 4.0 / 3.0).abs() < 1e-9);
+        assert!((stats.stddev - (2.0f64 / 9.0).sqrt()).abs() < 1e-9);
+        assert!((stats.max_over_mean - 1.5).abs() < 1e-9);
+        assert!((stats.gini - 1.0 / 6.0).abs() < 1e-9);
+
+        // tokens of one physical node add up
+        let mut hr: HashRing = HashRing::with_vnodes(16, 8);
+        hr.add_physical_node(1, 1).unwrap();
+        hr.add_physical_node(2, 3).unwrap();
+        let stats = hr.stats();
+        assert_eq!(vec![8, 24], stats.nodes.iter().map(|node| node.tokens).collect::<Vec<usize>>());
+        let total = stats.nodes.iter().map(|node| node.ownership).sum::<f64>();
+        assert!((total - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_migration_plan(){
+        let mut hr = HashRing::new(5);
+        for hash_value in [5, 12, 18]{
+            hr.add_node(RefCell::new(Node::new(hash_value)).into()).unwrap();
+        }
+        for hash_value in [2, 7, 10, 16, 21, 24, 29]{
+            hr.add_resource(hash_value).unwrap();
+        }
+        assert_eq!(Err(RingError::DuplicateNode(12)), hr.plan_add_node(&RefCell::new(Node::new(12)).into()));
+        assert_eq!(Err(RingError::UnknownNode(13)), hr.plan_remove_node(13));
+
+        let plan = hr.plan_add_node(&RefCell::new(Node::new(25)).into()).unwrap();
+        let moves = plan.moves.iter().map(|m| (m.key, m.from, m.to)).collect::<Vec<(u64, u64, u64)>>();
+        assert_eq!(vec![(21, 5, 25), (24, 5, 25)], moves);
+        // planning changes nothing
+        assert_eq!(5, hr.chord_lookup(21).unwrap().hash_value());
+
+        let mut applied = hr.add_node(RefCell::new(Node::new(25)).into()).unwrap();
+        applied.sort_by_key(|m| m.key);
+        assert_eq!(plan.moves, applied);
+
+        let plan = hr.plan_remove_node(25).unwrap();
+        assert_eq!(vec![21, 24], plan.moves.iter().map(|m| m.key).collect::<Vec<u64>>());
+        assert!(plan.moves.iter().all(|m| m.from == 25 && m.to == 5));
+        assert_eq!(Ok(()), hr.apply_plan(&plan));
+        assert_eq!(5, hr.chord_lookup(21).unwrap().hash_value());
+        assert_eq!(Err(RingError::StalePlan), hr.apply_plan(&plan));
+
+        // a plan goes stale once the resources it covers change
+        let plan = hr.plan_add_node(&RefCell::new(Node::new(9)).into()).unwrap();
+        assert_eq!(vec![7], plan.moves.iter().map(|m| m.key).collect::<Vec<u64>>());
+        hr.add_resource(8).unwrap();
+        assert_eq!(Err(RingError::StalePlan), hr.apply_plan(&plan));
+        assert_eq!(12, hr.chord_lookup(8).unwrap().hash_value());
+        assert_eq!(Ok(()), hr.apply_plan(&hr.plan_add_node(&RefCell::new(Node::new(9)).into()).unwrap()));
+        assert_eq!(9, hr.chord_lookup(8).unwrap().hash_value());
+    }
+
+    #[test]
+    fn test_migration_plan_with_replicas(){
+        let mut hr = HashRing::new(5);
+        hr.set_replication_factor(2);
+        for hash_value in [5, 12, 18]{
+            hr.add_node(RefCell::new(Node::new(hash_value)).into()).unwrap();
+        }
+        for hash_value in [2, 7, 10, 16, 21, 24, 29]{
+            hr.add_resource(hash_value).unwrap();
+        }
+
+        // a virtual node of physical node 12 keeps its identity through the plan
+        let joining = Node::new_virtual(25, 12).with_location(Location::new("zone-a", "rack-1"));
+        let plan = hr.plan_add_node(&RefCell::new(joining).into()).unwrap();
+        assert_eq!(12, plan.physical_id);
+        assert_eq!(Location::new("zone-a", "rack-1"), plan.location);
+        assert_eq!(vec![21, 24], plan.moves.iter().map(|m| m.key).collect::<Vec<u64>>());
+        // 21 and 24 now have their copy on 5; every other key prefers the
+        // new token, the only one outside the unlabelled zone
+        let copies = |copies: &[ReplicaCopy]| copies.iter().map(|copy| (copy.key, copy.node)).collect::<Vec<(u64, u64)>>();
+        assert_eq!(vec![(2, 25), (16, 25), (21, 5), (24, 5), (29, 25)], copies(&plan.replicas_added));
+        assert_eq!(vec![(2, 12), (16, 5), (21, 12), (24, 12), (29, 12)], copies(&plan.replicas_dropped));
+
+        hr.apply_plan(&plan).unwrap();
+        assert_eq!(vec![12, 25], hr.tokens_of(12));
+        assert_eq!(Location::new("zone-a", "rack-1"), hr.exact_node(25).unwrap().location());
+
+        // a leaving token's keys and copies land on the next physical nodes
+        let plan = hr.plan_remove_node(5).unwrap();
+        let before = hr.nodes().iter()
+            .flat_map(|node| node.replicas().into_keys().map(|key| (key, node.hash_value())).collect::<Vec<(u64, u64)>>())
+            .collect::<Vec<(u64, u64)>>();
+        hr.apply_plan(&plan).unwrap();
+        let after = hr.nodes().iter()
+            .flat_map(|node| node.replicas().into_keys().map(|key| (key, node.hash_value())).collect::<Vec<(u64, u64)>>())
+            .collect::<Vec<(u64, u64)>>();
+        for copy in plan.replicas_added.iter(){
+            assert!(!before.contains(&(copy.key, copy.node)) && after.contains(&(copy.key, copy.node)));
+        }
+        for copy in plan.replicas_dropped.iter(){
+            assert!(before.contains(&(copy.key, copy.node)) && !after.contains(&(copy.key, copy.node)));
+        }
+        assert!(!plan.replicas_added.is_empty() || !plan.replicas_dropped.is_empty());
+    }
+
+    // collects events into a log the test keeps a handle on
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl RingListener for Recorder{
+        fn on_node_joined(&mut self, hash_value: u64){
+            self.0.borrow_mut().push(format!("joined {}", hash_value));
+        }
+
+        fn on_node_left(&mut self, hash_value: u64){
+            self.0.borrow_mut().push(format!("left {}", hash_value));
+        }
+
+        fn on_resource_moved(&mut self, key: u64, from: u64, to: u64){
+            self.0.borrow_mut().push(format!("moved {} {}->{}", key, from, to));
+        }
+
+        fn on_finger_table_rebuilt(&mut self){
+            self.0.borrow_mut().push("fingers".to_string());
+        }
+    }
+
+    #[test]
+    fn test_listeners(){
+        let events = Rc::new(RefCell::new(vec![]));
+        let mut hr = HashRing::new(5);
+        hr.add_listener(Box::new(Recorder(events.clone())));
+        hr.add_node(RefCell::new(Node::new(18)).into()).unwrap();
+        hr.add_resource(10).unwrap();
+        hr.add_resource(16).unwrap();
+        hr.add_node(RefCell::new(Node::new(12)).into()).unwrap();
+        hr.build_finger_tables();
+        hr.remove_node(12).unwrap();
+        assert_eq!(
+            vec!["joined 18", "joined 12", "moved 10 18->12", "fingers", "left 12", "moved 10 12->18"],
+            *events.borrow()
+        );
+    }
+
+    #[test]
+    fn test_snapshot_restore(){
+        let mut hr = HashRing::with_seed(16, 8, 7);
+        for name in ["a:1", "b:1", "c:1"]{
+            hr.add_named_node(name, 1).unwrap();
+        }
+        for i in 0..500u64{
+            hr.add_resource(hr.hash_key(&format!("key:{}", i))).unwrap();
+        }
+        hr.set_replication_factor(2);
+        hr.build_finger_tables();
+
+        let bytes = hr.snapshot().to_bytes();
+        let restored = HashRing::restore(&RingSnapshot::from_bytes(&bytes).unwrap()).unwrap();
+        assert_eq!(hr.snapshot(), restored.snapshot());
+        for i in 0..2000u64{
+            let key = format!("key:{}", i);
+            assert_eq!(hr.node_for(&key), restored.node_for(&key));
+            let hash_value = hr.hash_key(&key);
+            assert_eq!(hr.chord_lookup(hash_value).unwrap().hash_value(), restored.chord_lookup(hash_value).unwrap().hash_value());
+        }
+        for (original, copy) in hr.nodes().iter().zip(restored.nodes().iter()){
+            assert_eq!(original.replicas(), copy.replicas());
+        }
+
+        assert_eq!(Err(SnapshotError::NotASnapshot), RingSnapshot::from_bytes(b"nope"));
+        assert_eq!(Err(SnapshotError::Truncated), RingSnapshot::from_bytes(&bytes[..bytes.len() - 1]));
+        let mut future = bytes.clone();
+        future[4] = 99;
+        assert_eq!(Err(SnapshotError::UnsupportedVersion(99)), RingSnapshot::from_bytes(&future));
+    }
+
+    #[test]
+    fn test_malformed_snapshot(){
+        let mut hr = HashRing::new(5);
+        hr.add_physical_node(1, 1).unwrap();
+        hr.add_resource(3).unwrap();
+        let bytes = hr.snapshot().to_bytes();
+
+        // k sits right after the magic and the version
+        for k in [0u32, 65, u32::MAX]{
+            let mut corrupt = bytes.clone();
+            corrupt[8..12].copy_from_slice(&k.to_le_bytes());
+            assert_eq!(Err(SnapshotError::InvalidK(k)), RingSnapshot::from_bytes(&corrupt));
+        }
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert_eq!(Err(SnapshotError::TrailingBytes), RingSnapshot::from_bytes(&longer));
+        for len in 0..bytes.len(){
+            assert!(RingSnapshot::from_bytes(&bytes[..len]).is_err());
+        }
+
+        // snapshots built by hand are checked on restore
+        let mut snapshot = hr.snapshot();
+        snapshot.k = 0;
+        assert_eq!(Some(SnapshotError::InvalidK(0)), HashRing::restore(&snapshot).err());
+        let mut snapshot = hr.snapshot();
+        snapshot.nodes[0].resources.push((32, 32));
+        assert_eq!(Some(SnapshotError::Ring(RingError::OutOfRange(32))), HashRing::restore(&snapshot).err());
+        let mut snapshot = hr.snapshot();
+        snapshot.nodes[0].hash_value = 40;
+        assert_eq!(Some(SnapshotError::Ring(RingError::OutOfRange(40))), HashRing::restore(&snapshot).err());
+
+        // a resource has to sit on the node owning its key
+        let mut two = HashRing::new(5);
+        two.add_node(RefCell::new(Node::new(10)).into()).unwrap();
+        two.add_node(RefCell::new(Node::new(20)).into()).unwrap();
+        two.add_resource(15).unwrap();
+        let mut snapshot = two.snapshot();
+        let key = snapshot.nodes[1].resources.pop().unwrap();
+        snapshot.nodes[0].resources.push(key);
+        assert_eq!(Some(SnapshotError::MisplacedResource(15)), HashRing::restore(&snapshot).err());
+
+        let mut unknown = two.snapshot().to_bytes();
+        // the policy follows the 20-byte header and the replication factor
+        unknown[28..32].copy_from_slice(&9u32.to_le_bytes());
+        assert_eq!(Err(SnapshotError::UnknownPolicy(9)), RingSnapshot::from_bytes(&unknown));
+    }
+
+    #[test]
+    fn test_snapshot_keeps_standalone_and_policy(){
+        let mut hr = HashRing::new(5);
+        hr.set_collision_policy(CollisionPolicy::Replace);
+        hr.add_node(RefCell::new(Node::new(12)).into()).unwrap();
+        hr.add_node(RefCell::new(Node::new_virtual(20, 3)).into()).unwrap();
+        let snapshot = RingSnapshot::from_bytes(&hr.snapshot().to_bytes()).unwrap();
+        assert_eq!(CollisionPolicy::Replace, snapshot.collision_policy);
+        assert_eq!(vec![true, false], snapshot.nodes.iter().map(|node| node.standalone).collect::<Vec<bool>>());
+        let restored = HashRing::restore(&snapshot).unwrap();
+        assert_eq!(hr.snapshot(), restored.snapshot());
+        assert_eq!(CollisionPolicy::Replace, restored.collision_policy());
+
+        // version 2 snapshots have neither
+        let mut bytes = b"RING".to_vec();
+        for field in [2u32, 5, 1, 0]{
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        for field in [1u64, 1, 12, 12, 0, 0, 0, 0]{
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        let snapshot = RingSnapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(CollisionPolicy::Reject, snapshot.collision_policy);
+        assert!(!snapshot.nodes[0].standalone);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_snapshot_json(){
+        let mut hr = HashRing::with_vnodes(12, 4);
+        hr.add_physical_node(1, 1).unwrap();
+        hr.add_physical_node(2, 1).unwrap();
+        for hash_value in (0..4096).step_by(97){
+            hr.add_resource(hash_value).unwrap();
+        }
+        hr.build_finger_tables();
+        let json = hr.snapshot().to_json();
+        assert!(json.contains("\"version\": 3"));
+        let restored = HashRing::restore(&RingSnapshot::from_json(&json).unwrap()).unwrap();
+        assert_eq!(hr.snapshot(), restored.snapshot());
+
+        let wide = json.replace("\"k\": 12", "\"k\": 65");
+        assert_eq!(Err(SnapshotError::InvalidK(65)), RingSnapshot::from_json(&wide));
+    }
+
+    #[test]
+    fn test_render(){
+        let mut hr = HashRing::new(5);
+        assert_eq!("digraph ring {\n    node [shape=circle];\n}\n", hr.to_dot());
+        for hash_value in [5, 12, 18]{
+            hr.add_node(RefCell::new(Node::new(hash_value)).into()).unwrap();
+        }
+        for hash_value in [2, 7, 10, 16]{
+            hr.add_resource(hash_value).unwrap();
+        }
+        hr.build_finger_tables();
+
+        let dot = hr.to_dot();
+        assert!(dot.contains("n12 [label=\"12\\n2 resources\""));
+        assert!(dot.contains("n18 -> n5 [style=bold];"));
+        assert!(dot.contains("n5 -> n12 [style=dashed"));
+
+        let svg = hr.to_svg();
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert_eq!(3, svg.matches("class=\"arc\"").count());
+        assert_eq!(4, svg.matches("class=\"resource\"").count());
+        assert_eq!(3, svg.matches("class=\"node\"").count());
+        let fingers = hr.nodes().iter().map(HashRing::finger_targets).map(|targets| targets.len()).sum::<usize>();
+        assert_eq!(fingers, svg.matches("class=\"finger\"").count());
+    }
+
+    #[test]
+    fn test_errors(){
+        let mut hr = HashRing::new(5);
+        assert!(matches!(hr.head(), Err(RingError::EmptyRing)));
+        assert!(matches!(hr.chord_lookup(3), Err(RingError::EmptyRing)));
+        assert_eq!(Err(RingError::EmptyRing), hr.add_resource(3));
+        assert_eq!(Err(RingError::OutOfRange(32)), hr.add_node(RefCell::new(Node::new(32)).into()));
+        assert!(matches!(hr.remove_node(3), Err(RingError::UnknownNode(3))));
+        assert_eq!(Err(RingError::UnknownNode(9)), hr.remove_physical_node(9));
+
+        let node: NodeRef = RefCell::new(Node::new(12)).into();
+        assert!(node.next().is_none());
+        hr.add_node(node.clone()).unwrap();
+        assert!(Rc::ptr_eq(&node, &node.next().unwrap()));
+        assert_eq!(Err(RingError::DuplicateNode(12)), hr.add_node(RefCell::new(Node::new(12)).into()));
+        assert!(matches!(hr.lookup_node(40), Err(RingError::OutOfRange(40))));
+        assert_eq!(Err(RingError::OutOfRange(40)), hr.add_resource(40));
+        assert_eq!("hash value 40 is out of range", RingError::OutOfRange(40).to_string());
+
+        hr.remove_node(12).unwrap();
+        assert!(node.previous().is_none());
+    }
+
+    #[test]
+    fn test_collision_policy(){
+        let mut hr = HashRing::new(5);
+        assert_eq!(CollisionPolicy::Reject, hr.collision_policy());
+        for hash_value in [5, 12, 18]{
+            hr.add_node(RefCell::new(Node::new(hash_value)).into()).unwrap();
+        }
+        for hash_value in [7, 10, 16]{
+            hr.add_resource(hash_value).unwrap();
+        }
+        hr.build_finger_tables();
+        assert_eq!(Err(RingError::DuplicateNode(12)), hr.add_node(RefCell::new(Node::new_virtual(12, 99)).into()));
+        assert_eq!(3, hr.nodes().len());
+
+        // the newcomer takes over position 12 and everything stored there
+        hr.set_collision_policy(CollisionPolicy::Replace);
+        let old = hr.chord_lookup(12).unwrap();
+        let moves = hr.add_node(RefCell::new(Node::new_virtual(12, 99)).into()).unwrap();
+        assert_eq!(vec![7, 10], moves.iter().map(|m| m.key).collect::<Vec<u64>>());
+        assert!(moves.iter().all(|m| m.from == 12 && m.to == 12));
+        assert_eq!(vec![5, 12, 18], hr.nodes().iter().map(|node| node.hash_value()).collect::<Vec<u64>>());
+        assert_eq!(Ok(99), hr.lookup_physical_node(10));
+        assert_eq!(vec![5, 18, 99], hr.physical_nodes());
+        assert!(old.next().is_none() && old.resources().is_empty());
+        for node in hr.nodes(){
+            assert!(node.finger_table().values().all(|finger| !Rc::ptr_eq(finger, &old)));
+        }
+
+        // the newcomer lands on the first free salted position, the same one every time
+        let mut placed = vec![];
+        for _ in 0..2{
+            let mut copy = HashRing::new(5);
+            copy.set_collision_policy(CollisionPolicy::Rehash);
+            for hash_value in [5, 12, 18]{
+                copy.add_node(RefCell::new(Node::new(hash_value)).into()).unwrap();
+            }
+            let node: NodeRef = RefCell::new(Node::new(18)).into();
+            // the plan follows the policy too
+            let plan = copy.plan_add_node(&node).unwrap();
+            copy.add_node(node.clone()).unwrap();
+            assert_ne!(18, node.hash_value());
+            assert_eq!(MembershipChange::Join(node.hash_value()), plan.change);
+            assert!(Rc::ptr_eq(&node, &copy.chord_lookup(node.hash_value()).unwrap()));
+            // a physical node of its own, not a second token of node 18
+            assert_eq!(node.hash_value(), node.physical_id());
+            assert_eq!(4, copy.physical_nodes().len());
+            assert_eq!(vec![18], copy.tokens_of(18));
+            placed.push(node.hash_value());
+        }
+        assert_eq!(placed[0], placed[1]);
+
+        // a full ring runs out of salts instead of searching forever
+        let mut full: HashRing = HashRing::new(2);
+        full.set_collision_policy(CollisionPolicy::Rehash);
+        for hash_value in 0..4{
+            full.add_node(RefCell::new(Node::new(hash_value)).into()).unwrap();
+        }
+        assert!(matches!(full.add_node(RefCell::new(Node::new(1)).into()), Err(RingError::DuplicateNode(1))));
+        assert_eq!(4, full.nodes().len());
+    }
+
+    #[test]
+    fn test_replace_keeps_physical_nodes(){
+        let mut hr = HashRing::new(5);
+        hr.set_collision_policy(CollisionPolicy::Replace);
+        for hash_value in [5, 12, 18]{
+            hr.add_node(RefCell::new(Node::new(hash_value)).into()).unwrap();
+        }
+        hr.add_node(RefCell::new(Node::new_virtual(25, 5)).into()).unwrap();
+        hr.add_resource(10).unwrap();
+
+        // planned and applied, the takeover moves token 12 to physical node 99
+        let plan = hr.plan_add_node(&RefCell::new(Node::new_virtual(12, 99)).into()).unwrap();
+        assert_eq!(vec![ResourceMove { key: 10, from: 12, to: 12 }], plan.moves);
+        hr.apply_plan(&plan).unwrap();
+        assert_eq!(vec![5, 18, 99], hr.physical_nodes());
+        assert_eq!(vec![12], hr.tokens_of(99));
+
+        // physical node 5 loses one of its two tokens
+        hr.add_node(RefCell::new(Node::new_virtual(25, 18)).into()).unwrap();
+        assert_eq!(vec![5], hr.tokens_of(5));
+        assert_eq!(vec![18, 25], hr.tokens_of(18));
+        assert_eq!(Vec::<Violation>::new(), hr.validate());
+
+        // a standalone node can replace itself, but not claim an id with other tokens
+        hr.add_node(RefCell::new(Node::new(5)).into()).unwrap();
+        assert_eq!(vec![5], hr.tokens_of(5));
+        assert_eq!(Err(RingError::DuplicateNode(18)), hr.add_node(RefCell::new(Node::new(18)).into()));
+        assert_eq!(vec![18, 25], hr.tokens_of(18));
+        assert_eq!(Vec::<Violation>::new(), hr.validate());
+
+        // the very node already on the ring is refused under every policy
+        let node: NodeRef = RefCell::new(Node::new(30)).into();
+        hr.add_node(node.clone()).unwrap();
+        for policy in [CollisionPolicy::Reject, CollisionPolicy::Replace, CollisionPolicy::Rehash]{
+            hr.set_collision_policy(policy);
+            assert_eq!(Err(RingError::DuplicateNode(30)), hr.add_node(node.clone()));
+            assert_eq!(Err(RingError::DuplicateNode(30)), hr.plan_add_node(&node).map(|_| ()));
+        }
+        assert_eq!(vec![30], hr.tokens_of(30));
+        assert_eq!(Vec::<Violation>::new(), hr.validate());
+    }
+
+    #[test]
+    fn test_remove_node(){
+        let mut hr = HashRing::new(5);
+        for hash_value in [5, 12, 18, 27, 30]{
+            hr.add_node(RefCell::new(Node::new(hash_value)).into()).unwrap();
+        }
+        hr.build_finger_tables();
+        for hash_value in [2, 7, 10, 16, 21, 23, 24, 28, 29]{
+            hr.add_resource(hash_value).unwrap();
+        }
+
+        assert!(matches!(hr.remove_node(13), Err(RingError::UnknownNode(13))));
+
+        let departed = hr.remove_node(27).unwrap();
+        assert!(departed.resources().is_empty());
+        assert!(departed.finger_table().is_empty());
+        let successor = hr.chord_lookup(21).unwrap();
+        assert_eq!(30, successor.hash_value());
+        let mut keys = successor.resources().keys().copied().collect::<Vec<u64>>();
+        keys.sort();
+        assert_eq!(vec![21, 23, 24, 28, 29], keys);
+
+        // no finger is left pointing at the departed node
+        for node in hr.nodes(){
+            assert!(node.inspect_finger_table().iter().all(|(_, v)| *v != 27));
+        }
+
+        // head leaves, its successor becomes the new head
+        hr.remove_node(5).unwrap();
+        assert_eq!(12, hr.head().unwrap().hash_value());
+        assert_eq!(12, hr.chord_lookup(2).unwrap().hash_value());
+        assert_eq!(12, hr.chord_lookup(31).unwrap().hash_value());
+        assert_eq!(30, hr.chord_lookup(29).unwrap().hash_value());
+    }
+
+    #[test]
+    fn test_key_value(){
+        let mut hr: KeyValueRing<String, String> = HashRing::with_seed(16, 8, 3);
+        hr.set_replication_factor(2);
+        assert_eq!(Err(RingError::EmptyRing), hr.get("missing"));
+
+        hr.add_named_node("10.0.0.1:7000", 1).unwrap();
+        hr.add_named_node("10.0.0.2:7000", 1).unwrap();
+        for i in 0..50{
+            hr.put(format!("user:{}", i), format!("value {}", i)).unwrap();
+        }
+        assert_eq!(Ok(Some("value 7".to_string())), hr.get("user:7"));
+        assert_eq!(Ok(None), hr.get("missing"));
+
+        // values follow their keys through a join and a leave
+        hr.add_named_node("10.0.0.3:7000", 1).unwrap();
+        for i in 0..50{
+            assert_eq!(Ok(Some(format!("value {}", i))), hr.get(&format!("user:{}", i)));
+        }
+        let alpha = hr.hash_key("10.0.0.1:7000");
+        hr.remove_physical_node(alpha).unwrap();
+        for i in 0..50{
+            assert_eq!(Ok(Some(format!("value {}", i))), hr.get(&format!("user:{}", i)));
+        }
+
+        // delete drops the owner's copy and every replica
+        let hash_value = hr.hash_key("user:7");
+        assert_eq!(Ok(Some("value 7".to_string())), hr.delete("user:7"));
+        assert_eq!(Ok(None), hr.get("user:7"));
+        assert_eq!(Ok(None), hr.delete("user:7"));
+        assert!(hr.nodes().iter().all(|node| !node.resources().contains_key(&hash_value) && !node.replicas().contains_key(&hash_value)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_k_out_of_range(){
+        HashRing::<u64>::new(0);
+    }
+
+    #[test]
+    fn test_colliding_keys(){
+        // 16 positions for 40 keys
+        let mut hr: KeyValueRing<String, u64> = HashRing::new(4);
+        hr.set_replication_factor(2);
+        hr.add_physical_node(1, 1).unwrap();
+        hr.add_physical_node(2, 1).unwrap();
+        for i in 0..40{
+            hr.put(format!("key:{}", i), i).unwrap();
+        }
+        for i in 0..40{
+            assert_eq!(Ok(Some(i)), hr.get(&format!("key:{}", i)));
+        }
+
+        let (a, b) = (0..40).flat_map(|i| (i + 1..40).map(move |j| (format!("key:{}", i), format!("key:{}", j))))
+            .find(|(a, b)| hr.hash_key(a) == hr.hash_key(b))
+            .unwrap();
+        hr.put(a.clone(), 100).unwrap();
+        assert_eq!(Ok(Some(100)), hr.get(&a));
+        assert_ne!(Ok(Some(100)), hr.get(&b));
+        assert_eq!(Ok(None), hr.get("missing"));
+
+        // deleting one key keeps the other, on the owner and on the replica
+        assert_eq!(Ok(Some(100)), hr.delete(&a));
+        assert_eq!(Ok(None), hr.get(&a));
+        assert!(hr.get(&b).unwrap().is_some());
+        let hash_value = hr.hash_key(&b);
+        let holders = hr.nodes().iter()
+            .filter(|node| node.replicas().get(&hash_value).is_some_and(|entries| entries.iter().any(|(key, _)| *key == b)))
+            .count();
+        assert_eq!(1, holders);
+    }
+
+    #[test]
+    fn test_resources_in_range(){
+        let mut hr = HashRing::new(5);
+        for hash_value in [5, 12, 18, 27, 30]{
+            hr.add_node(RefCell::new(Node::new(hash_value)).into()).unwrap();
+        }
+        hr.build_finger_tables();
+        for hash_value in [2, 7, 10, 16, 21, 23, 24, 28, 29]{
+            hr.add_resource(hash_value).unwrap();
+        }
+        let keys = |a, b| hr.resources_in_range(a, b).unwrap().into_iter()
+            .map(|(hash_value, _)| hash_value).collect::<Vec<u64>>();
+
+        assert_eq!(vec![7, 10, 16], keys(7, 16));
+        assert_eq!(Vec::<u64>::new(), keys(19, 20));
+        assert_eq!(vec![21], keys(21, 21));
+        // wrapping past zero
+        assert_eq!(vec![28, 29, 2, 7], keys(28, 7));
+        assert_eq!(vec![7, 10, 16, 21, 23, 24, 28, 29, 2], keys(6, 5));
+        // both ends fall on node 5, which is visited once
+        assert_eq!(vec![7, 10, 16, 21, 23, 24, 28, 29], keys(3, 1));
+
+        assert_eq!(Err(RingError::OutOfRange(40)), hr.resources_in_range(3, 40));
+        let empty: HashRing = HashRing::new(5);
+        assert_eq!(Err(RingError::EmptyRing), empty.resources_in_range(3, 4));
+    }
+
+    #[test]
+    fn test_zone_aware_replicas(){
+        let mut hr = HashRing::with_vnodes(16, 4);
+        hr.set_replication_factor(3);
+        // two racks in each of three zones
+        for physical_id in 1..=6{
+            let zone = format!("zone-{}", physical_id % 3);
+            let rack = format!("rack-{}", physical_id % 2);
+            hr.add_physical_node_at(physical_id, 1, Location::new(&zone, &rack)).unwrap();
+        }
+        let keys = (0..hr.max).step_by(101).collect::<Vec<u64>>();
+        for hash_value in keys.iter(){
+            hr.add_resource(*hash_value).unwrap();
+        }
+        for hash_value in keys.iter(){
+            let mut zones = hr.replicas_for(*hash_value).unwrap().iter().map(|node| node.location().zone).collect::<Vec<String>>();
+            zones.sort();
+            zones.dedup();
+            assert_eq!(3, zones.len());
+        }
+        assert!(hr.colocation_report().is_empty());
+
+        // with zone-0 gone two copies have to share a zone, but not a rack
+        hr.remove_physical_node(3).unwrap();
+        hr.remove_physical_node(6).unwrap();
+        let report = hr.colocation_report();
+        assert_eq!(keys.len(), report.len());
+        for colocation in report.iter(){
+            assert_eq!(3, colocation.holders.len());
+            assert_eq!(1, colocation.zones.len());
+            assert!(!colocation.same_rack);
+        }
+
+        // labels survive a snapshot
+        let restored = HashRing::restore(&RingSnapshot::from_bytes(&hr.snapshot().to_bytes()).unwrap()).unwrap();
+        assert_eq!(report, restored.colocation_report());
+
+        // version 1 snapshots carry no labels
+        let mut bytes = b"RING".to_vec();
+        for field in [1u32, 5, 1, 0]{
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        for field in [1u64, 1, 12, 12, 0, 0]{
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        let snapshot = RingSnapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(SNAPSHOT_VERSION, snapshot.version);
+        let restored: HashRing = HashRing::restore(&snapshot).unwrap();
+        assert_eq!(Location::default(), restored.head().unwrap().location());
+    }
+
+    #[test]
+    fn test_64_bit_ring(){
+        let mut hr = HashRing::with_vnodes(64, 8);
+        assert_eq!(u64::MAX, hr.max);
+        assert_eq!(1, hr.distance(u64::MAX, 0));
+        assert_eq!(u64::MAX, hr.distance(1, 0));
+        for name in ["a:1", "b:1", "c:1", "d:1"]{
+            hr.add_named_node(name, 1).unwrap();
+        }
+        hr.build_finger_tables();
+        // the finger for 2^63 wraps past zero
+        let head = hr.head().unwrap();
+        let target = head.hash_value().wrapping_add(1 << 63);
+        assert_eq!(hr.lookup_node(target).unwrap().hash_value(), head.finger_table()[&(1 << 63)].hash_value());
+
+        let mut above_32_bits = 0;
+        for i in 0..500u64{
+            let key = format!("key:{}", i);
+            let hash_value = hr.put(key.clone(), i).unwrap();
+            above_32_bits += (hash_value > u32::MAX as u64) as usize;
+            assert_eq!(hr.lookup_node(hash_value).unwrap().hash_value(), hr.chord_lookup(hash_value).unwrap().hash_value());
+            assert_eq!(Ok(Some(i)), hr.get(&key));
+        }
+        assert!(above_32_bits > 400);
+        for hash_value in [0, 1, u64::MAX - 1, u64::MAX]{
+            assert_eq!(hr.lookup_node(hash_value).unwrap().hash_value(), hr.chord_lookup(hash_value).unwrap().hash_value());
+        }
+        assert_eq!(1.0, hr.stats().nodes.iter().map(|node| node.ownership).sum::<f64>().round());
+    }
+
+    #[test]
+    fn test_validate(){
+        fn node(hr: &HashRing, hash_value: u64) -> NodeRef{
+            hr.lookup_node(hash_value).unwrap()
+        }
+
+        let mut hr = HashRing::new(5);
+        assert!(hr.validate().is_empty());
+        for hash_value in [5, 12, 18, 27, 30]{
+            hr.add_node(RefCell::new(Node::new(hash_value)).into()).unwrap();
+        }
+        for hash_value in [2, 7, 10, 16, 21]{
+            hr.add_resource(hash_value).unwrap();
+        }
+        hr.build_finger_tables();
+        assert!(hr.validate().is_empty());
+
+        // the finger for 4 from 30 is the successor of 2
+        node(&hr, 30).set_finger(4, node(&hr, 12));
+        node(&hr, 18).insert_resource(3, 3);
+        assert_eq!(vec![
+            Violation::MisplacedResource { key: 3, node: 18, owner: 5 },
+            Violation::WrongFinger { node: 30, range: 4, finger: 12, expected: 5 },
+        ], hr.validate());
+        node(&hr, 30).set_finger(4, node(&hr, 5));
+        node(&hr, 18).remove_resource(3);
+
+        // a new node leaves the fingers of the others stale until a rebuild
+        hr.add_node(RefCell::new(Node::new(8)).into()).unwrap();
+        assert!(hr.validate().contains(&Violation::WrongFinger { node: 5, range: 1, finger: 12, expected: 8 }));
+        hr.build_finger_tables();
+        assert!(hr.validate().is_empty());
+
+        node(&hr, 12).set_previous(node(&hr, 18));
+        hr.head = Some(node(&hr, 12));
+        assert_eq!(vec![
+            Violation::OutOfOrder { node: 30, next: 5 },
+            Violation::BrokenLink { node: 8, next: 12 },
+            Violation::HeadNotMinimum { head: 12, minimum: 5 },
+        ], hr.validate());
+    }
+
+    #[test]
+    fn test_random_operations_keep_invariants(){
+        for seed in 0..10{
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut hr = HashRing::with_vnodes(10, 2);
+            hr.set_replication_factor(2);
+            for step in 0..100{
+                match rng.random_range(0..12){
+                    0 => hr.set_collision_policy([CollisionPolicy::Reject, CollisionPolicy::Replace, CollisionPolicy::Rehash][rng.random_range(0..3)]),
+                    1 | 2 => {
+                        // ids already on the ring, and under `Reject` taken tokens, are refused
+                        if let Err(error) = hr.add_physical_node(rng.random_range(0..1024), rng.random_range(1..3)){
+                            assert!(matches!(error, RingError::DuplicateNode(_)), "seed {} step {}", seed, step);
+                        }
+                    },
+                    3 if !hr.physical_nodes().is_empty() => {
+                        let physical_ids = hr.physical_nodes();
+                        hr.remove_physical_node(physical_ids[rng.random_range(0..physical_ids.len())]).unwrap();
+                    },
+                    4 => {
+                        if let Ok(plan) = hr.plan_add_node(&RefCell::new(Node::new(rng.random_range(0..1024))).into()){
+                            hr.apply_plan(&plan).unwrap();
+                        }
+                    },
+                    5 if !hr.nodes().is_empty() => {
+                        let nodes = hr.nodes();
+                        hr.remove_node(nodes[rng.random_range(0..nodes.len())].hash_value()).unwrap();
+                    },
+                    _ => {
+                        if !hr.nodes().is_empty(){
+                            hr.add_resource(hr.hash_key(&rng.random::<u64>())).unwrap();
+                        }
+                        assert_eq!(Vec::<Violation>::new(), hr.validate(), "seed {} step {}", seed, step);
+                        continue;
+                    },
+                }
+                // fingers are rebuilt after membership changes, as Chord's stabilisation would
+                hr.build_finger_tables();
+                assert_eq!(Vec::<Violation>::new(), hr.validate(), "seed {} step {}", seed, step);
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::c02_key_hash::space_max;
+use crate::c02_ring_error::RingError;
+
+/// A token on the ring. Unlike the linked-list rings, nodes do not point at
+/// each other: neighbours are found through the sorted token map.
+#[derive(Clone)]
+pub struct Node{
+    hash_value: u64,
+    resources: HashMap<u64, u64>,
+}
+
+impl Node{
+    pub fn new(hash_value: u64) -> Self{
+        Self { hash_value, resources: HashMap::new() }
+    }
+
+    pub fn hash_value(&self) -> u64{
+        self.hash_value
+    }
+
+    pub fn resources(&self) -> &HashMap<u64, u64>{
+        &self.resources
+    }
+}
+
+/// Ring backed by a `BTreeMap` from token to node, so finding the successor of
+/// a hash is a range query in O(log n) instead of a walk along `next()`.
+#[derive(Clone)]
+pub struct HashRing{
+    nodes: BTreeMap<u64, Node>,
+    min: u64,
+    max: u64,
+}
+
+impl HashRing{
+    pub fn new(k: u32) -> Self{
+        Self { nodes: BTreeMap::new(), min: 0, max: space_max(k) }
+    }
+
+    pub fn is_in_legal_range(&self, hash_value: u64) -> bool {
+        hash_value >= self.min && hash_value <= self.max
+    }
+
+    /// Clockwise distance from `a` to `b`, wrapping modulo 2^k.
+    pub fn distance(&self, a: u64, b: u64) -> u64{
+        b.wrapping_sub(a) & self.max
+    }
+
+    pub fn len(&self) -> usize{
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.nodes.is_empty()
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Node>{
+        self.nodes.values()
+    }
+
+    pub fn contains_node(&self, hash_value: u64) -> bool{
+        self.nodes.contains_key(&hash_value)
+    }
+
+    /// Token of the first node at or after `hash_value`, wrapping past the top.
+    fn successor(&self, hash_value: u64) -> Option<u64>{
+        self.nodes.range(hash_value..).next()
+            .or_else(|| self.nodes.iter().next())
+            .map(|(token, _)| *token)
+    }
+
+    pub fn lookup_node(&self, hash_value: u64) -> Result<&Node, RingError>{
+        if !self.is_in_legal_range(hash_value){
+            return Err(RingError::OutOfRange(hash_value));
+        }
+        let token = self.successor(hash_value).ok_or(RingError::EmptyRing)?;
+        Ok(&self.nodes[&token])
+    }
+
+    pub fn add_resource(&mut self, hash_value: u64) -> Result<(), RingError>{
+        let token = self.lookup_node(hash_value)?.hash_value();
+        self.nodes.get_mut(&token).unwrap().resources.insert(hash_value, hash_value);
+        Ok(())
+    }
+
+    fn move_resources(&mut self, dest: u64, orig: u64, delete_true: bool){
+        let moved = self.nodes[&orig].resources.iter()
+            .filter(|(i, _)| self.distance(**i, dest) < self.distance(**i, orig) || delete_true)
+            .map(|(i, j)| (*i, *j))
+            .collect::<Vec<(u64, u64)>>();
+
+        for (i, _) in moved.iter(){
+            self.nodes.get_mut(&orig).unwrap().resources.remove(i);
+        }
+        self.nodes.get_mut(&dest).unwrap().resources.extend(moved);
+    }
+
+    pub fn add_node(&mut self, hash_value: u64) -> Result<(), RingError>{
+        if !self.is_in_legal_range(hash_value){
+            return Err(RingError::OutOfRange(hash_value));
+        }
+        if self.nodes.contains_key(&hash_value){
+            return Err(RingError::DuplicateNode(hash_value));
+        }
+        let successor = self.successor(hash_value);
+        self.nodes.insert(hash_value, Node::new(hash_value));
+        if let Some(successor) = successor{
+            self.move_resources(hash_value, successor, false);
+        }
+        Ok(())
+    }
+
+    /// Removes the node at `hash_value` and hands its resources to its
+    /// successor. Returns the departed node.
+    pub fn remove_node(&mut self, hash_value: u64) -> Result<Node, RingError>{
+        if !self.nodes.contains_key(&hash_value){
+            return Err(RingError::UnknownNode(hash_value));
+        }
+        if self.nodes.len() > 1{
+            let successor = self.successor(hash_value.wrapping_add(1) & self.max).unwrap();
+            self.move_resources(successor, hash_value, true);
+        }
+        Ok(self.nodes.remove(&hash_value).unwrap())
+    }
+
+    pub fn print_hash_ring(&self){
+        println!("****");
+        if self.nodes.is_empty(){
+            println!("Empty hash ring");
+            return;
+        }
+        for node in self.nodes.values(){
+            println!("Node hash value: {}", node.hash_value());
+            println!("Resources: {:?}", node.resources().keys().collect::<Vec<&u64>>());
+        }
+        println!("****");
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    #[should_panic]
+    fn test_k_out_of_range(){
+        HashRing::new(0);
+    }
+
+    #[test]
+    fn test_scenario(){
+        let mut hr = HashRing::new(5);
+        assert_eq!(Err(RingError::EmptyRing), hr.add_resource(3));
+        hr.add_node(12).unwrap();
+        hr.add_node(18).unwrap();
+        assert_eq!(Err(RingError::DuplicateNode(18)), hr.add_node(18));
+        assert_eq!(Err(RingError::OutOfRange(32)), hr.add_node(32));
+        for hash_value in [24, 21, 16, 23, 2, 29, 28, 7, 10]{
+            hr.add_resource(hash_value).unwrap();
+        }
+        assert_eq!(12, hr.lookup_node(24).unwrap().hash_value());
+        assert_eq!(18, hr.lookup_node(16).unwrap().hash_value());
+        assert_eq!(12, hr.lookup_node(12).unwrap().hash_value());
+        assert!(matches!(hr.lookup_node(40), Err(RingError::OutOfRange(40))));
+
+        hr.add_node(5).unwrap();
+        hr.add_node(27).unwrap();
+        hr.add_node(30).unwrap();
+        hr.print_hash_ring();
+        let mut keys = hr.lookup_node(5).unwrap().resources().keys().copied().collect::<Vec<u64>>();
+        keys.sort();
+        assert_eq!(vec![2], keys);
+        assert_eq!(2, hr.lookup_node(30).unwrap().resources().len());
+
+        let departed = hr.remove_node(30).unwrap();
+        assert!(departed.resources().is_empty());
+        assert_eq!(3, hr.lookup_node(31).unwrap().resources().len());
+        assert!(matches!(hr.remove_node(30), Err(RingError::UnknownNode(30))));
+    }
+
+    #[test]
+    fn performance_test(){
+        let start= std::time::Instant::now();
+        let mut rng = StdRng::seed_from_u64(5000);
+        let mut hr = HashRing::new(20);
+        let max = hr.max;
+
+        for _ in 0..5000{
+            let random_value: u64 = rng.random::<u64>() % max;
+            // collisions are expected with this many random tokens
+            let _ = hr.add_node(random_value);
+        }
+
+        for _ in 0..50000{
+            let random_value: u64 = rng.random::<u64>() % max;
+            hr.add_resource(random_value).unwrap();
+        }
+
+        let duration = start.elapsed();
+        println!("Time elapsed in expensive_function() is: {:?}", duration);
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::c02_ring_error::RingError;
+use crate::c02_ringhash_3::HashRing;
+
+/// A ring that can be shared between threads.
+///
+/// Readers work on an immutable snapshot (`Arc<HashRing>`), so a lookup never
+/// sees a membership change half way through. Writers are serialised, copy the
+/// current ring, apply their change to the copy and swap it in. Every write
+/// therefore costs a full copy of the ring; this is meant for read-heavy use
+/// where membership changes are rare.
+pub struct ConcurrentHashRing{
+    current: RwLock<Arc<HashRing>>,
+    writer: Mutex<()>,
+}
+
+impl ConcurrentHashRing{
+    pub fn new(k: u32) -> Self{
+        Self { current: RwLock::new(Arc::new(HashRing::new(k))), writer: Mutex::new(()) }
+    }
+
+    /// The ring as of now. It stays valid and unchanged however long it is held.
+    pub fn snapshot(&self) -> Arc<HashRing>{
+        self.current.read().unwrap().clone()
+    }
+
+    /// Token of the node owning `hash_value`.
+    pub fn lookup(&self, hash_value: u64) -> Result<u64, RingError>{
+        self.snapshot().lookup_node(hash_value).map(|node| node.hash_value())
+    }
+
+    // a refused change leaves the published ring as it was
+    fn update<T>(&self, change: impl FnOnce(&mut HashRing) -> Result<T, RingError>) -> Result<T, RingError>{
+        let _guard = self.writer.lock().unwrap();
+        let mut next = HashRing::clone(&self.snapshot());
+        let result = change(&mut next)?;
+        *self.current.write().unwrap() = Arc::new(next);
+        Ok(result)
+    }
+
+    pub fn add_node(&self, hash_value: u64) -> Result<(), RingError>{
+        self.update(|ring| ring.add_node(hash_value))
+    }
+
+    pub fn remove_node(&self, hash_value: u64) -> Result<(), RingError>{
+        self.update(|ring| ring.remove_node(hash_value).map(|_| ()))
+    }
+
+    pub fn add_resource(&self, hash_value: u64) -> Result<(), RingError>{
+        self.update(|ring| ring.add_resource(hash_value))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::{sync::atomic::{AtomicBool, Ordering}, thread};
+
+    #[test]
+    fn test_is_send_and_sync(){
+        fn assert_send_sync<T: Send + Sync>(){}
+        assert_send_sync::<ConcurrentHashRing>();
+    }
+
+    #[test]
+    fn test_lookup_while_membership_changes(){
+        let ring = Arc::new(ConcurrentHashRing::new(16));
+        assert_eq!(Err(RingError::EmptyRing), ring.lookup(3));
+        // a few nodes that never leave, so the ring is never empty
+        for hash_value in [1000, 30000, 60000]{
+            ring.add_node(hash_value).unwrap();
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut writers = vec![];
+        for writer in 0..2u64{
+            let ring = ring.clone();
+            writers.push(thread::spawn(move || {
+                for round in 0..300u64{
+                    let hash_value = (writer * 7919 + round * 104729) % 65536;
+                    // the other writer may have placed the same token already
+                    let _ = ring.add_node(hash_value);
+                    ring.add_resource((hash_value + 17) % 65536).unwrap();
+                    if round % 2 == 0 && ![1000, 30000, 60000].contains(&hash_value){
+                        let _ = ring.remove_node(hash_value);
+                    }
+                }
+            }));
+        }
+
+        let mut readers = vec![];
+        for reader in 0..4u64{
+            let ring = ring.clone();
+            let stop = stop.clone();
+            readers.push(thread::spawn(move || {
+                let mut lookups = 0;
+                let mut hash_value = reader;
+                while !stop.load(Ordering::Relaxed) || lookups < 1000{
+                    hash_value = hash_value.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407) % 65536;
+                    let snapshot = ring.snapshot();
+                    let token = snapshot.lookup_node(hash_value).unwrap().hash_value();
+                    assert!(snapshot.contains_node(token));
+                    // the owner is the first member at or after the key, wrapping around
+                    let expected = snapshot.nodes().map(|node| node.hash_value())
+                        .find(|token| *token >= hash_value)
+                        .unwrap_or_else(|| snapshot.nodes().next().unwrap().hash_value());
+                    assert_eq!(expected, token);
+                    lookups += 1;
+                }
+                lookups
+            }));
+        }
+
+        for writer in writers{
+            writer.join().unwrap();
+        }
+        stop.store(true, Ordering::Relaxed);
+        for reader in readers{
+            assert!(reader.join().unwrap() >= 1000);
+        }
+
+        let snapshot = ring.snapshot();
+        for hash_value in (0..65536).step_by(13){
+            assert!(snapshot.contains_node(ring.lookup(hash_value).unwrap()));
+        }
+    }
+}
@@ -1,3 +1,20 @@
-mod c02_ringhash_1;
-mod c02_ringhash_2;
-mod c03_bloom_filter_mod_filter;
\ No newline at end of file
+pub mod c02_bounded_load;
+pub mod c02_churn_simulation;
+pub mod c02_consistent_hasher;
+pub mod c02_jump_hash;
+pub mod c02_key_hash;
+pub mod c02_maglev;
+pub mod c02_migration;
+pub mod c02_multi_probe;
+pub mod c02_rendezvous_hash;
+pub mod c02_ring_backend;
+pub mod c02_ring_error;
+pub mod c02_ring_listener;
+pub mod c02_ring_snapshot;
+pub mod c02_ringhash_1;
+pub mod c02_ringhash_2;
+pub mod c02_ringhash_3;
+pub mod c02_ringhash_concurrent;
+// chapter 3 code, left as it was; it predates these lints
+#[allow(dead_code, clippy::ptr_arg, clippy::single_component_path_imports, clippy::unnecessary_cast)]
+mod c03_bloom_filter_mod_filter;